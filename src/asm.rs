@@ -0,0 +1,767 @@
+use cpu::rom::Rom;
+use disasm::{Disassembler, Syntax};
+use number::{parse_address, parse_number};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub const USAGE: &'static str = "Usage: chip8 asm <source> -o <rom> [--schip | --xo]
+       chip8 asm --roundtrip <rom>... [--schip | --xo]
+
+Options:
+    -o <path>                 Where to write the assembled ROM
+    --schip                   Allow SUPER-CHIP instructions
+    --xo                      Allow XO-CHIP instructions, which include
+                              SUPER-CHIP's
+    --roundtrip               Disassemble each ROM, reassemble the result and
+                              check that the bytes are unchanged";
+
+const PROGRAM_START: usize = 0x200;
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // The low 12 bits of an instruction word
+    Address,
+    // The whole word following F000
+    LongAddress,
+}
+
+// A reference to a label that may not have been defined yet
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+// Assembles the Octo dialect that `disasm` produces: one statement after another,
+// with `#` comments, `: label` definitions and bare numbers as data bytes.
+pub struct Assembler {
+    schip: bool,
+    xo_chip: bool,
+    tokens: Vec<Token>,
+    pos: usize,
+    output: Vec<u8>,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+}
+
+impl Assembler {
+    // XO-CHIP includes the SUPER-CHIP instructions, so `xo_chip` allows both
+    pub fn new(source: &str, schip: bool, xo_chip: bool) -> Assembler {
+        Assembler {
+            schip: schip || xo_chip,
+            xo_chip: xo_chip,
+            tokens: tokenize(source),
+            pos: 0,
+            output: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    pub fn assemble(mut self) -> Result<Vec<u8>, AsmError> {
+        while self.pos < self.tokens.len() {
+            try!(self.statement());
+        }
+
+        for fixup in self.fixups.iter() {
+            let addr = match self.labels.get(&fixup.name) {
+                Some(addr) => *addr,
+                None => {
+                    return Err(AsmError {
+                        line: fixup.line,
+                        column: fixup.column,
+                        message: format!("undefined label '{}'", fixup.name),
+                    })
+                }
+            };
+
+            match fixup.kind {
+                FixupKind::Address => {
+                    if addr > 0xfff {
+                        return Err(AsmError {
+                            line: fixup.line,
+                            column: fixup.column,
+                            message: format!("'{}' is at {:#x}, out of reach of a 12-bit address; \
+                                              use 'i := long'",
+                                             fixup.name,
+                                             addr),
+                        });
+                    }
+                    self.output[fixup.offset] |= (addr >> 8) as u8;
+                    self.output[fixup.offset + 1] = addr as u8;
+                }
+                FixupKind::LongAddress => {
+                    self.output[fixup.offset] = (addr >> 8) as u8;
+                    self.output[fixup.offset + 1] = addr as u8;
+                }
+            }
+        }
+
+        Ok(self.output)
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.pos;
+        let text = self.tokens[token].text.clone();
+        self.pos += 1;
+
+        match text.as_str() {
+            ":" => {
+                let name = try!(self.identifier());
+                if self.labels.contains_key(&name) {
+                    return Err(self.error_at(token, format!("label '{}' is already defined", name)));
+                }
+                let addr = self.address();
+                self.labels.insert(name, addr);
+            }
+            ":const" => {
+                let name = try!(self.identifier());
+                let value = try!(self.value(0xffff));
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = try!(self.identifier());
+                let register = try!(self.register());
+                self.aliases.insert(name, register);
+            }
+            ":byte" => {
+                let value = try!(self.value(0xff));
+                self.output.push(value as u8);
+            }
+            ":org" => {
+                let addr = try!(self.value(0xffff)) as usize;
+                let current = PROGRAM_START + self.output.len();
+                if addr < current {
+                    return Err(self.error_at(token, format!("cannot move back to {:#x}", addr)));
+                }
+                self.output.resize(addr - PROGRAM_START, 0);
+            }
+            ":call" => try!(self.address_op(0x2000)),
+            "clear" => self.emit(0x00e0),
+            "return" => self.emit(0x00ee),
+            "scroll-down" => {
+                try!(self.require_schip(token));
+                let n = try!(self.value(0xf));
+                self.emit(0x00c0 | n);
+            }
+            "scroll-up" => {
+                try!(self.require_xo(token));
+                let n = try!(self.value(0xf));
+                self.emit(0x00d0 | n);
+            }
+            "scroll-right" | "scroll-left" | "exit" | "lores" | "hires" => {
+                try!(self.require_schip(token));
+                let word = match text.as_str() {
+                    "scroll-right" => 0x00fb,
+                    "scroll-left" => 0x00fc,
+                    "exit" => 0x00fd,
+                    "lores" => 0x00fe,
+                    _ => 0x00ff,
+                };
+                self.emit(word);
+            }
+            "jump" => try!(self.address_op(0x1000)),
+            "jump0" => try!(self.address_op(0xb000)),
+            "if" => try!(self.conditional()),
+            "save" | "load" => {
+                let x = try!(self.register()) as u16;
+                if self.peek() == Some("-") {
+                    try!(self.require_xo(token));
+                    self.pos += 1;
+                    let y = try!(self.register()) as u16;
+                    let n = if text == "save" { 2 } else { 3 };
+                    self.emit(0x5000 | (x << 8) | (y << 4) | n);
+                } else {
+                    let kk = if text == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xf000 | (x << 8) | kk);
+                }
+            }
+            "saveflags" | "loadflags" => {
+                try!(self.require_schip(token));
+                let kk = if text == "saveflags" { 0x75 } else { 0x85 };
+                try!(self.register_op(0xf000 | kk));
+            }
+            "bcd" => try!(self.register_op(0xf033)),
+            "sprite" => {
+                let x = try!(self.register()) as u16;
+                let y = try!(self.register()) as u16;
+                let n = try!(self.value(0xf));
+                self.emit(0xd000 | (x << 8) | (y << 4) | n);
+            }
+            "plane" => {
+                try!(self.require_xo(token));
+                let n = try!(self.value(0xf));
+                self.emit(0xf001 | (n << 8));
+            }
+            "audio" => {
+                try!(self.require_xo(token));
+                self.emit(0xf002);
+            }
+            "pitch" => {
+                try!(self.require_xo(token));
+                try!(self.expect(":="));
+                try!(self.register_op(0xf03a));
+            }
+            "delay" => {
+                try!(self.expect(":="));
+                try!(self.register_op(0xf015));
+            }
+            "buzzer" => {
+                try!(self.expect(":="));
+                try!(self.register_op(0xf018));
+            }
+            "i" => try!(self.index_op()),
+            _ => {
+                if let Some(x) = self.register_named(&text) {
+                    try!(self.register_statement(x));
+                } else if let Some(value) = parse_number(&text) {
+                    if value > 0xff {
+                        return Err(self.error_at(token, format!("byte {} is out of range", text)));
+                    }
+                    self.output.push(value as u8);
+                } else if is_identifier(&text) {
+                    // A bare name calls the subroutine with that label
+                    self.pos -= 1;
+                    try!(self.address_op(0x2000));
+                } else {
+                    return Err(self.error_at(token, format!("unexpected '{}'", text)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Octo's conditionals name the case in which the next statement runs, which is
+    // the opposite of the skip instruction they assemble to
+    fn conditional(&mut self) -> Result<(), AsmError> {
+        let x = try!(self.register()) as u16;
+        let operator_token = self.pos;
+        let operator = try!(self.next_text());
+
+        match operator.as_str() {
+            "key" => self.emit(0xe0a1 | (x << 8)),
+            "-key" => self.emit(0xe09e | (x << 8)),
+            "==" | "!=" => {
+                let equal = operator == "==";
+                let operand = try!(self.next_text());
+                self.pos -= 1;
+                if let Some(y) = self.register_named(&operand) {
+                    self.pos += 1;
+                    let base = if equal { 0x9000 } else { 0x5000 };
+                    self.emit(base | (x << 8) | ((y as u16) << 4));
+                } else {
+                    let kk = try!(self.value(0xff));
+                    let base = if equal { 0x4000 } else { 0x3000 };
+                    self.emit(base | (x << 8) | kk);
+                }
+            }
+            _ => {
+                return Err(self.error_at(operator_token,
+                                         format!("expected ==, !=, key or -key, got '{}'",
+                                                 operator)))
+            }
+        }
+
+        self.expect("then")
+    }
+
+    fn index_op(&mut self) -> Result<(), AsmError> {
+        let operator_token = self.pos;
+        let operator = try!(self.next_text());
+
+        match operator.as_str() {
+            ":=" => {
+                let form = self.peek().unwrap_or("").to_string();
+                match form.as_str() {
+                    "long" => {
+                        try!(self.require_xo(self.pos));
+                        self.pos += 1;
+                        self.emit(0xf000);
+                        self.emit(0x0000);
+                        self.target(FixupKind::LongAddress)
+                    }
+                    "hex" => {
+                        self.pos += 1;
+                        self.register_op(0xf029)
+                    }
+                    "bighex" => {
+                        try!(self.require_schip(self.pos));
+                        self.pos += 1;
+                        self.register_op(0xf030)
+                    }
+                    _ => self.address_op(0xa000),
+                }
+            }
+            "+=" => self.register_op(0xf01e),
+            _ => {
+                Err(self.error_at(operator_token,
+                                  format!("expected := or += after i, got '{}'", operator)))
+            }
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let x = x as u16;
+        let operator_token = self.pos;
+        let operator = try!(self.next_text());
+
+        let alu = match operator.as_str() {
+            ":=" => 0x0,
+            "|=" => 0x1,
+            "&=" => 0x2,
+            "^=" => 0x3,
+            "+=" => 0x4,
+            "-=" => 0x5,
+            ">>=" => 0x6,
+            "=-" => 0x7,
+            "<<=" => 0xe,
+            _ => {
+                return Err(self.error_at(operator_token,
+                                         format!("unknown operator '{}'", operator)))
+            }
+        };
+
+        let operand = try!(self.next_text());
+        if let Some(y) = self.register_named(&operand) {
+            self.emit(0x8000 | (x << 8) | ((y as u16) << 4) | alu);
+            return Ok(());
+        }
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "random") => {
+                let kk = try!(self.value(0xff));
+                self.emit(0xc000 | (x << 8) | kk);
+            }
+            (":=", "delay") => self.emit(0xf007 | (x << 8)),
+            (":=", "key") => self.emit(0xf00a | (x << 8)),
+            (":=", _) | ("+=", _) => {
+                self.pos -= 1;
+                let kk = try!(self.value(0xff));
+                let base = if operator == ":=" { 0x6000 } else { 0x7000 };
+                self.emit(base | (x << 8) | kk);
+            }
+            _ => {
+                return Err(self.error_at(self.pos - 1,
+                                         format!("'{}' needs a register, got '{}'",
+                                                 operator,
+                                                 operand)))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_op(&mut self, base: u16) -> Result<(), AsmError> {
+        let x = try!(self.register()) as u16;
+        self.emit(base | (x << 8));
+        Ok(())
+    }
+
+    fn address_op(&mut self, base: u16) -> Result<(), AsmError> {
+        self.emit(base);
+        self.target(FixupKind::Address)
+    }
+
+    // Fills in the address of the instruction just emitted, now or once the label is known
+    fn target(&mut self, kind: FixupKind) -> Result<(), AsmError> {
+        let token = self.pos;
+        let text = try!(self.next_text());
+        let offset = self.output.len() - 2;
+
+        let value = match parse_address(&text) {
+            Some(value) => Some(value),
+            None => self.constants.get(&text).cloned(),
+        };
+
+        match value {
+            Some(addr) => {
+                let limit = match kind {
+                    FixupKind::Address => 0xfff,
+                    FixupKind::LongAddress => 0xffff,
+                };
+                if addr > limit {
+                    return Err(self.error_at(token, format!("address {} is out of range", text)));
+                }
+                self.output[offset] |= (addr >> 8) as u8;
+                self.output[offset + 1] = addr as u8;
+            }
+            None if is_identifier(&text) => {
+                self.fixups.push(Fixup {
+                    offset: offset,
+                    kind: kind,
+                    name: text,
+                    line: self.tokens[token].line,
+                    column: self.tokens[token].column,
+                });
+            }
+            None => return Err(self.error_at(token, format!("expected an address, got '{}'", text))),
+        }
+
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) {
+        self.output.push((word >> 8) as u8);
+        self.output.push(word as u8);
+    }
+
+    fn address(&self) -> u16 {
+        (PROGRAM_START + self.output.len()) as u16
+    }
+
+    fn require_schip(&self, token: usize) -> Result<(), AsmError> {
+        if self.schip {
+            Ok(())
+        } else {
+            Err(self.error_at(token,
+                              format!("'{}' is a SUPER-CHIP instruction; pass --schip to allow it",
+                                      self.tokens[token].text)))
+        }
+    }
+
+    fn require_xo(&self, token: usize) -> Result<(), AsmError> {
+        if self.xo_chip {
+            Ok(())
+        } else {
+            Err(self.error_at(token,
+                              format!("'{}' is an XO-CHIP instruction; pass --xo to allow it",
+                                      self.tokens[token].text)))
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text.as_str())
+    }
+
+    fn next_text(&mut self) -> Result<String, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.text.clone())
+            }
+            None => Err(self.error_at_end("unexpected end of file")),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.pos;
+        let text = try!(self.next_text());
+        if text != expected {
+            return Err(self.error_at(token, format!("expected '{}', got '{}'", expected, text)));
+        }
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<String, AsmError> {
+        let token = self.pos;
+        let text = try!(self.next_text());
+        if !is_identifier(&text) || self.register_named(&text).is_some() {
+            return Err(self.error_at(token, format!("'{}' cannot be used as a name", text)));
+        }
+        Ok(text)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.pos;
+        let text = try!(self.next_text());
+        self.register_named(&text)
+            .ok_or(self.error_at(token, format!("expected a register, got '{}'", text)))
+    }
+
+    fn register_named(&self, text: &str) -> Option<u8> {
+        let lower = text.to_lowercase();
+        if lower.len() == 2 && lower.starts_with('v') {
+            if let Ok(x) = u8::from_str_radix(&lower[1..], 16) {
+                return Some(x);
+            }
+        }
+        self.aliases.get(text).cloned()
+    }
+
+    // A number or a constant defined earlier
+    fn value(&mut self, max: u16) -> Result<u16, AsmError> {
+        let token = self.pos;
+        let text = try!(self.next_text());
+        let value = match parse_address(&text) {
+            Some(value) => value,
+            None => {
+                try!(self.constants
+                         .get(&text)
+                         .cloned()
+                         .ok_or(self.error_at(token, format!("expected a number, got '{}'", text))))
+            }
+        };
+
+        if value > max {
+            return Err(self.error_at(token, format!("{} is out of range, maximum {:#x}", text, max)));
+        }
+        Ok(value)
+    }
+
+    fn error_at(&self, token: usize, message: String) -> AsmError {
+        match self.tokens.get(token) {
+            Some(token) => {
+                AsmError {
+                    line: token.line,
+                    column: token.column,
+                    message: message,
+                }
+            }
+            None => self.error_at_end(&message),
+        }
+    }
+
+    fn error_at_end(&self, message: &str) -> AsmError {
+        let (line, column) = match self.tokens.last() {
+            Some(token) => (token.line, token.column + token.text.len()),
+            None => (1, 1),
+        };
+        AsmError {
+            line: line,
+            column: column,
+            message: message.to_string(),
+        }
+    }
+}
+
+pub fn assemble(source: &str, schip: bool, xo_chip: bool) -> Result<Vec<u8>, AsmError> {
+    Assembler::new(source, schip, xo_chip).assemble()
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, raw_line) in source.lines().enumerate() {
+        let line = match raw_line.find('#') {
+            Some(pos) => &raw_line[..pos],
+            None => raw_line,
+        };
+
+        let mut start = None;
+        for (column, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(column),
+                (Some(begin), true) => {
+                    tokens.push(Token {
+                        text: line[begin..column].to_string(),
+                        line: index + 1,
+                        column: begin + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tokens
+}
+
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+pub fn run<I: Iterator<Item = String>>(mut args: I) -> Result<(), String> {
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut schip = false;
+    let mut xo_chip = false;
+    let mut roundtrip = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(try!(args.next().ok_or("-o expects a path".to_string()))),
+            "--schip" => schip = true,
+            "--xo" => xo_chip = true,
+            "--roundtrip" => roundtrip = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    if roundtrip {
+        if inputs.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        return check_roundtrip(&inputs, schip, xo_chip);
+    }
+
+    let (source_path, output) = match (inputs.len(), output) {
+        (1, Some(output)) => (inputs.remove(0), output),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let mut source = String::new();
+    try!(File::open(&source_path)
+             .and_then(|mut f| f.read_to_string(&mut source))
+             .map_err(|e| format!("Could not read {}: {}", source_path, e)));
+
+    let rom = try!(assemble(&source, schip, xo_chip).map_err(|e| format!("{}:{}", source_path, e)));
+
+    try!(File::create(&output)
+             .and_then(|mut f| f.write_all(&rom))
+             .map_err(|e| format!("Could not write {}: {}", output, e)));
+    println!("Wrote {} bytes to {}", rom.len(), output);
+    Ok(())
+}
+
+// Disassembling a ROM and assembling the result should give back the same bytes
+fn check_roundtrip(roms: &[String], schip: bool, xo_chip: bool) -> Result<(), String> {
+    let mut failures = 0;
+
+    for path in roms {
+        let rom = try!(Rom::from_path(Path::new(path))
+                           .map_err(|e| format!("Could not read {}: {}", path, e)));
+        let rom = rom.data();
+
+        let source = Disassembler::new(rom, xo_chip).render(Syntax::Octo);
+        match assemble(&source, schip, xo_chip) {
+            Ok(ref assembled) if &assembled[..] == rom => println!("ok        {}", path),
+            Ok(assembled) => {
+                let offset = rom.iter()
+                                .zip(assembled.iter())
+                                .position(|(a, b)| a != b)
+                                .unwrap_or(rom.len().min(assembled.len()));
+                println!("MISMATCH  {} at {:#x}", path, PROGRAM_START + offset);
+                failures += 1;
+            }
+            Err(e) => {
+                println!("ERROR     {}: disassembly does not assemble: {}", path, e);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(format!("{} of {} ROMs did not round-trip", failures, roms.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The fixtures use every instruction of their platform, with sprite data after the
+    // code and, in the XO-CHIP one, bytes no path reaches
+    fn assert_roundtrip(rom: &[u8], schip: bool, xo_chip: bool) {
+        let source = Disassembler::new(rom, xo_chip).render(Syntax::Octo);
+        match assemble(&source, schip, xo_chip) {
+            Ok(assembled) => assert_eq!(&assembled[..], rom, "source was:\n{}", source),
+            Err(e) => panic!("disassembly does not assemble: {}\nsource was:\n{}", e, source),
+        }
+    }
+
+    #[test]
+    fn chip8_rom_roundtrips() {
+        assert_roundtrip(include_bytes!("../tests/fixtures/chip8.ch8"), false, false);
+    }
+
+    #[test]
+    fn schip_rom_roundtrips() {
+        assert_roundtrip(include_bytes!("../tests/fixtures/schip.ch8"), true, false);
+    }
+
+    #[test]
+    fn xo_chip_rom_roundtrips() {
+        assert_roundtrip(include_bytes!("../tests/fixtures/xo_chip.ch8"), false, true);
+    }
+
+
+    fn error_in(source: &str) -> AsmError {
+        match assemble(source, false, false) {
+            Ok(rom) => panic!("expected an error, assembled {:?}", rom),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn schip_instructions_need_schip_or_xo() {
+        let sources = ["scroll-down 4", "scroll-right", "scroll-left", "exit", "lores", "hires",
+                       "saveflags v3", "loadflags v3", "i := bighex v1"];
+        for source in sources.iter() {
+            let error = error_in(source);
+            assert!(error.message.contains("SUPER-CHIP"), "{}: {}", source, error);
+            assert!(assemble(source, true, false).is_ok(), "{}", source);
+            assert!(assemble(source, false, true).is_ok(), "{}", source);
+        }
+
+        let error = error_in("v0 := 1\ni := bighex v0");
+        assert_eq!((error.line, error.column), (2, 6));
+    }
+
+    #[test]
+    fn xo_instructions_need_xo() {
+        let error = error_in("clear\n  plane 2");
+        assert_eq!((error.line, error.column), (2, 3));
+        assert!(assemble("plane 2", true, false).is_err());
+        assert!(assemble("plane 2", false, true).is_ok());
+    }
+
+    #[test]
+    fn dxy0_is_allowed_on_plain_chip8() {
+        assert_eq!(assemble("sprite v1 v2 0", false, false).unwrap(), vec![0xd1, 0x20]);
+    }
+
+    #[test]
+    fn errors_point_at_unknown_mnemonics() {
+        let error = error_in("clear\n\n   v0 := 1 @draw");
+        assert_eq!((error.line, error.column), (3, 12));
+        assert_eq!(error.message, "unexpected '@draw'");
+    }
+
+    #[test]
+    fn errors_point_at_out_of_range_immediates() {
+        let error = error_in("v3 := 0x100");
+        assert_eq!((error.line, error.column), (1, 7));
+
+        let error = error_in("clear\nsprite v0 v1 16");
+        assert_eq!((error.line, error.column), (2, 14));
+
+        let error = error_in("jump 0x1000");
+        assert_eq!((error.line, error.column), (1, 6));
+    }
+
+    #[test]
+    fn errors_point_at_undefined_labels() {
+        let error = error_in(": main\n  jump main\n  i := sprite\n  draw-score\n: sprite");
+        assert_eq!((error.line, error.column), (4, 3));
+        assert_eq!(error.message, "undefined label 'draw-score'");
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// A small INI-style configuration file:
+//
+//     # comment
+//     platform = schip_modern
+//
+//     [quirks]
+//     clip_sprites = off
+//
+// Keys before the first section header belong to the unnamed section "".
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+pub struct ConfigEntry {
+    pub section: String,
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+impl Config {
+    pub fn empty() -> Config {
+        Config { entries: Vec::new() }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let mut file = try!(File::open(path)
+                                .map_err(|e| format!("Could not open {}: {}", path.display(), e)));
+        let mut text = String::new();
+        try!(file.read_to_string(&mut text)
+                 .map_err(|e| format!("Could not read {}: {}", path.display(), e)));
+
+        Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+
+        for (index, raw_line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = match raw_line.find('#') {
+                Some(pos) => raw_line[..pos].trim(),
+                None => raw_line.trim(),
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(format!("line {}: unterminated section header", line_number));
+                }
+                section = line[1..line.len() - 1].trim().to_string();
+                if section.is_empty() {
+                    return Err(format!("line {}: empty section name", line_number));
+                }
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(v) => v.trim(),
+                None => return Err(format!("line {}: expected 'key = value'", line_number)),
+            };
+
+            if key.is_empty() {
+                return Err(format!("line {}: missing key", line_number));
+            }
+            if key.contains(char::is_whitespace) {
+                return Err(format!("line {}: invalid key '{}'", line_number, key));
+            }
+
+            entries.push(ConfigEntry {
+                section: section.clone(),
+                key: key.to_string(),
+                value: value.to_string(),
+                line: line_number,
+            });
+        }
+
+        Ok(Config { entries: entries })
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.section == section && e.key == key)
+            .map(|e| e.value.as_str())
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    pub fn section(&self, section: &str) -> Vec<&ConfigEntry> {
+        self.entries.iter().filter(|e| e.section == section).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_sections_and_skips_comments() {
+        let config = Config::parse("# settings\n\
+                                    platform = schip_modern  # trailing comment\n\
+                                    \n\
+                                    [quirks]\n\
+                                    clip_sprites = off\n\
+                                    [ audio ]\n\
+                                    tone=440\n")
+                         .unwrap();
+
+        assert_eq!(config.get("", "platform"), Some("schip_modern"));
+        assert_eq!(config.get("quirks", "clip_sprites"), Some("off"));
+        assert_eq!(config.get("audio", "tone"), Some("440"));
+        assert_eq!(config.get("", "clip_sprites"), None);
+        assert_eq!(config.section("quirks")[0].line, 5);
+    }
+
+    #[test]
+    fn later_values_win() {
+        let config = Config::parse("ipf = 10\nipf = 20\n").unwrap();
+        assert_eq!(config.get("", "ipf"), Some("20"));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        assert_eq!(Config::parse("ipf = 10\n[quirks\n").err(),
+                   Some("line 2: unterminated section header".to_string()));
+        assert_eq!(Config::parse("[]\n").err(), Some("line 1: empty section name".to_string()));
+        assert_eq!(Config::parse("platform\n").err(),
+                   Some("line 1: expected 'key = value'".to_string()));
+        assert_eq!(Config::parse("= 10\n").err(), Some("line 1: missing key".to_string()));
+        assert_eq!(Config::parse("key wait = press\n").err(),
+                   Some("line 1: invalid key 'key wait'".to_string()));
+    }
+}
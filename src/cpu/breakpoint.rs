@@ -0,0 +1,556 @@
+use super::register::Registers;
+use super::memory::Memory;
+
+use std::collections::btree_map::{self, BTreeMap};
+use std::fmt;
+
+// How an instruction touches memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+            AccessKind::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+// The kinds of access a watchpoint reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Access {
+    // Parses a combination of r, w and x, such as "rw"
+    pub fn from_name(name: &str) -> Option<Access> {
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut access = Access {
+            read: false,
+            write: false,
+            execute: false,
+        };
+
+        for c in name.chars() {
+            match c {
+                'r' => access.read = true,
+                'w' => access.write = true,
+                'x' => access.execute = true,
+                _ => return None,
+            }
+        }
+
+        Some(access)
+    }
+
+    pub fn includes(&self, kind: AccessKind) -> bool {
+        match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+            AccessKind::Execute => self.execute,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}{}{}",
+               if self.read { "r" } else { "" },
+               if self.write { "w" } else { "" },
+               if self.execute { "x" } else { "" })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    V(u8),
+    I,
+    Pc,
+    // Depth of the call stack
+    Sp,
+    Dt,
+    St,
+    Memory(u16),
+}
+
+impl Operand {
+    pub fn value(&self, reg: &Registers, mem: &Memory) -> Option<u16> {
+        match *self {
+            Operand::V(x) => Some(reg.read_register(x) as u16),
+            Operand::I => Some(reg.read_register_i()),
+            Operand::Pc => Some(reg.read_pc()),
+            Operand::Sp => Some(reg.stack().len() as u16),
+            Operand::Dt => Some(reg.read_delay_timer() as u16),
+            Operand::St => Some(reg.read_sound_timer() as u16),
+            Operand::Memory(addr) => mem.read_byte(addr).ok().map(|byte| byte as u16),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::V(x) => write!(f, "V{:X}", x),
+            Operand::I => write!(f, "I"),
+            Operand::Pc => write!(f, "PC"),
+            Operand::Sp => write!(f, "SP"),
+            Operand::Dt => write!(f, "DT"),
+            Operand::St => write!(f, "ST"),
+            Operand::Memory(addr) => write!(f, "[{:#05x}]", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    pub fn from_name(name: &str) -> Option<Comparison> {
+        match name {
+            "==" => Some(Comparison::Eq),
+            "!=" => Some(Comparison::Ne),
+            "<" => Some(Comparison::Lt),
+            "<=" => Some(Comparison::Le),
+            ">" => Some(Comparison::Gt),
+            ">=" => Some(Comparison::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+// A comparison such as `V3 == 0x10`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub operand: Operand,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, reg: &Registers, mem: &Memory) -> bool {
+        let current = match self.operand.value(reg, mem) {
+            Some(current) => current,
+            None => return false,
+        };
+
+        match self.comparison {
+            Comparison::Eq => current == self.value,
+            Comparison::Ne => current != self.value,
+            Comparison::Lt => current < self.value,
+            Comparison::Le => current <= self.value,
+            Comparison::Gt => current > self.value,
+            Comparison::Ge => current >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:#x}", self.operand, self.comparison.name(), self.value)
+    }
+}
+
+// A class of opcodes written like the usual notation, such as `Dxyn` or `Fx1E`. Hex
+// digits must match and any other character matches any nibble.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    bits: u16,
+    text: String,
+}
+
+impl OpcodePattern {
+    pub fn parse(text: &str) -> Option<OpcodePattern> {
+        if text.chars().count() != 4 {
+            return None;
+        }
+
+        let mut mask = 0;
+        let mut bits = 0;
+        for c in text.chars() {
+            mask <<= 4;
+            bits <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xf;
+                bits |= digit as u16;
+            } else if !c.is_alphabetic() && c != '?' {
+                return None;
+            }
+        }
+
+        Some(OpcodePattern {
+            mask: mask,
+            bits: bits,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn matches(&self, word: u16) -> bool {
+        word & self.mask == self.bits
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    // Reaching an address
+    Address(u16),
+    // An instruction touching any byte from `start` to `end` inclusive
+    Watch { start: u16, end: u16, access: Access },
+    Opcode(OpcodePattern),
+    // The condition becoming true. It only triggers again once it has been false.
+    Condition(Condition),
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Trigger::Address(addr) => write!(f, "break at {:#05x}", addr),
+            Trigger::Watch { start, end, access } if start == end => {
+                write!(f, "watch {:#05x} {}", start, access)
+            }
+            Trigger::Watch { start, end, access } => {
+                write!(f, "watch {:#05x}-{:#05x} {}", start, end, access)
+            }
+            Trigger::Opcode(ref pattern) => write!(f, "break on {}", pattern),
+            Trigger::Condition(ref condition) => write!(f, "break when {}", condition),
+        }
+    }
+}
+
+pub struct Breakpoint {
+    pub trigger: Trigger,
+    // Only stops while this holds
+    pub condition: Option<Condition>,
+    // Number of upcoming hits that don't stop
+    pub ignore: usize,
+    pub hits: usize,
+    // Last value of a `Trigger::Condition`
+    held: bool,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.trigger));
+        if let Some(ref condition) = self.condition {
+            try!(write!(f, " if {}", condition));
+        }
+        try!(write!(f, "  (hits: {}", self.hits));
+        if self.ignore > 0 {
+            try!(write!(f, ", ignoring next {}", self.ignore));
+        }
+        write!(f, ")")
+    }
+}
+
+// Why execution stopped, reported before the instruction at `pc` runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub id: usize,
+    pub pc: u16,
+    // The access that set off a watchpoint
+    pub access: Option<(AccessKind, u16)>,
+}
+
+// A memory range the next instruction touches
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub addr: u16,
+    pub len: usize,
+}
+
+// Everything about the upcoming instruction that breakpoints are checked against
+pub struct Pending<'a> {
+    pub reg: &'a Registers,
+    pub mem: &'a Memory,
+    pub word: Option<u16>,
+    pub accesses: &'a [MemoryAccess],
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    next_id: usize,
+    list: BTreeMap<usize, Breakpoint>,
+}
+
+impl Breakpoints {
+    // Returns the new breakpoint's number
+    pub fn add(&mut self, trigger: Trigger, condition: Option<Condition>) -> usize {
+        self.next_id += 1;
+        self.list.insert(self.next_id,
+                         Breakpoint {
+                             trigger: trigger,
+                             condition: condition,
+                             ignore: 0,
+                             hits: 0,
+                             held: false,
+                         });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.list.remove(&id).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.list.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Breakpoint> {
+        self.list.get_mut(&id)
+    }
+
+    pub fn iter<'a>(&'a self) -> btree_map::Iter<'a, usize, Breakpoint> {
+        self.list.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn has_address(&self, addr: u16) -> bool {
+        self.list.values().any(|bp| bp.trigger == Trigger::Address(addr))
+    }
+
+    // Every breakpoint is evaluated, so that hit counts and condition edges stay
+    // current, and the first one that stops is returned
+    pub fn check(&mut self, pending: &Pending) -> Option<Hit> {
+        let pc = pending.reg.read_pc();
+        let mut first = None;
+
+        for (&id, bp) in self.list.iter_mut() {
+            let (triggered, access) = match bp.trigger {
+                Trigger::Address(addr) => (addr == pc, None),
+                Trigger::Watch { start, end, access } => {
+                    let touched = find_access(pending.accesses, start, end, access);
+                    (touched.is_some(), touched)
+                }
+                Trigger::Opcode(ref pattern) => {
+                    (pending.word.map_or(false, |word| pattern.matches(word)), None)
+                }
+                Trigger::Condition(ref condition) => {
+                    let holds = condition.holds(pending.reg, pending.mem);
+                    let rising = holds && !bp.held;
+                    bp.held = holds;
+                    (rising, None)
+                }
+            };
+
+            if !triggered {
+                continue;
+            }
+            if let Some(ref condition) = bp.condition {
+                if !condition.holds(pending.reg, pending.mem) {
+                    continue;
+                }
+            }
+
+            bp.hits += 1;
+            if bp.ignore > 0 {
+                bp.ignore -= 1;
+                continue;
+            }
+
+            if first.is_none() {
+                first = Some(Hit {
+                    id: id,
+                    pc: pc,
+                    access: access,
+                });
+            }
+        }
+
+        first
+    }
+}
+
+fn find_access(accesses: &[MemoryAccess],
+               start: u16,
+               end: u16,
+               access: Access)
+               -> Option<(AccessKind, u16)> {
+    for touched in accesses.iter().filter(|a| access.includes(a.kind)) {
+        for offset in 0..touched.len {
+            let addr = touched.addr.wrapping_add(offset as u16);
+            if addr >= start && addr <= end {
+                return Some((touched.kind, addr));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(breakpoints: &mut Breakpoints,
+             reg: &Registers,
+             accesses: &[MemoryAccess])
+             -> Option<Hit> {
+        let mem = Memory::default();
+        breakpoints.check(&Pending {
+            reg: reg,
+            mem: &mem,
+            word: None,
+            accesses: accesses,
+        })
+    }
+
+    #[test]
+    fn access_from_name_takes_any_combination_of_rwx() {
+        let rw = Access::from_name("rw").unwrap();
+        assert!(rw.read && rw.write && !rw.execute);
+        assert_eq!(rw.to_string(), "rw");
+
+        let x = Access::from_name("x").unwrap();
+        assert!(!x.read && !x.write && x.execute);
+        assert_eq!(Access::from_name("xwr").unwrap().to_string(), "rwx");
+
+        assert_eq!(Access::from_name(""), None);
+        assert_eq!(Access::from_name("rq"), None);
+        assert_eq!(Access::from_name("W"), None);
+    }
+
+    #[test]
+    fn opcode_patterns_match_hex_digits_and_wildcards() {
+        let draw = OpcodePattern::parse("Dxyn").unwrap();
+        assert!(draw.matches(0xd123));
+        assert!(draw.matches(0xdfff));
+        assert!(!draw.matches(0xc123));
+
+        let add_i = OpcodePattern::parse("Fx1E").unwrap();
+        assert!(add_i.matches(0xf31e));
+        assert!(!add_i.matches(0xf31f));
+        assert!(!add_i.matches(0xe31e));
+
+        let clear = OpcodePattern::parse("00e0").unwrap();
+        assert!(clear.matches(0x00e0));
+        assert!(!clear.matches(0x00ee));
+        assert!(OpcodePattern::parse("??EE").unwrap().matches(0x00ee));
+
+        assert_eq!(OpcodePattern::parse("D12"), None);
+        assert_eq!(OpcodePattern::parse("Dxyn0"), None);
+        assert_eq!(OpcodePattern::parse("D-yn"), None);
+    }
+
+    #[test]
+    fn condition_triggers_only_trigger_when_they_become_true() {
+        let mut breakpoints = Breakpoints::default();
+        let condition = Condition {
+            operand: Operand::V(0),
+            comparison: Comparison::Eq,
+            value: 1,
+        };
+        let id = breakpoints.add(Trigger::Condition(condition), None);
+        let mut reg = Registers::new();
+
+        assert_eq!(check(&mut breakpoints, &reg, &[]), None);
+        reg.write_register(0, 1);
+        assert_eq!(check(&mut breakpoints, &reg, &[]).map(|hit| hit.id), Some(id));
+        assert_eq!(check(&mut breakpoints, &reg, &[]), None);
+        reg.write_register(0, 2);
+        assert_eq!(check(&mut breakpoints, &reg, &[]), None);
+        reg.write_register(0, 1);
+        assert_eq!(check(&mut breakpoints, &reg, &[]).map(|hit| hit.id), Some(id));
+        assert_eq!(breakpoints.get(id).unwrap().hits, 2);
+    }
+
+    #[test]
+    fn ignored_hits_count_down_before_stopping() {
+        let mut breakpoints = Breakpoints::default();
+        let reg = Registers::new();
+        let id = breakpoints.add(Trigger::Address(reg.read_pc()), None);
+        breakpoints.get_mut(id).unwrap().ignore = 2;
+
+        assert_eq!(check(&mut breakpoints, &reg, &[]), None);
+        assert_eq!(breakpoints.get(id).unwrap().ignore, 1);
+        assert_eq!(check(&mut breakpoints, &reg, &[]), None);
+        assert_eq!(check(&mut breakpoints, &reg, &[]).map(|hit| hit.id), Some(id));
+        assert_eq!(check(&mut breakpoints, &reg, &[]).map(|hit| hit.id), Some(id));
+        assert_eq!(breakpoints.get(id).unwrap().hits, 4);
+        assert_eq!(breakpoints.get(id).unwrap().ignore, 0);
+    }
+
+    #[test]
+    fn watchpoints_report_the_first_watched_byte_touched() {
+        let read = Access::from_name("r").unwrap();
+        let write = Access::from_name("w").unwrap();
+        let accesses = [MemoryAccess {
+                            kind: AccessKind::Read,
+                            addr: 0x300,
+                            len: 4,
+                        }];
+
+        assert_eq!(find_access(&accesses, 0x302, 0x305, read),
+                   Some((AccessKind::Read, 0x302)));
+        assert_eq!(find_access(&accesses, 0x2f0, 0x300, read),
+                   Some((AccessKind::Read, 0x300)));
+        assert_eq!(find_access(&accesses, 0x303, 0x303, read),
+                   Some((AccessKind::Read, 0x303)));
+        assert_eq!(find_access(&accesses, 0x304, 0x310, read), None);
+        assert_eq!(find_access(&accesses, 0x2f0, 0x2ff, read), None);
+        assert_eq!(find_access(&accesses, 0x300, 0x303, write), None);
+    }
+
+    #[test]
+    fn watchpoints_stop_with_the_access() {
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add(Trigger::Watch {
+                                     start: 0x400,
+                                     end: 0x40f,
+                                     access: Access::from_name("w").unwrap(),
+                                 },
+                                 None);
+        let accesses = [MemoryAccess {
+                            kind: AccessKind::Write,
+                            addr: 0x3fe,
+                            len: 3,
+                        }];
+
+        let hit = check(&mut breakpoints, &Registers::new(), &accesses).unwrap();
+        assert_eq!(hit.id, id);
+        assert_eq!(hit.access, Some((AccessKind::Write, 0x400)));
+    }
+}
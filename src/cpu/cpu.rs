@@ -1,869 +1,844 @@
-use super::register::Registers;
-use super::keypad::Keypad;
-use super::memory::Memory;
+use super::machine::Machine;
+use super::error::{EmuError, FrameOutcome};
+use super::debugger::{self, Action, RunMode};
+use super::gdb::{GdbStub, Request, Resume};
+use super::font::FontSet;
+use super::keypad::KeyWaitPolicy;
+use super::quirks::Quirks;
+use super::random::RandomMode;
+use super::rewind::RewindBuffer;
+use super::rom::Rom;
+use super::trace::{TraceFilter, Tracer};
+
+use frontend::{Frontend, InputEvent};
+use movie::{Movie, MovieEvent, MovieHeader, MovieRecorder};
 
 use std::fmt;
 use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
+
+use time;
+
+
+const FRAMES_PER_SECOND: u64 = 60;
+const NANOS_PER_FRAME: u64 = 1_000_000_000 / FRAMES_PER_SECOND;
+// How far the run loop may fall behind real time before giving up on catching up
+const MAX_FRAME_LAG: u64 = 5 * NANOS_PER_FRAME;
+
+const STATE_SLOTS: u8 = 10;
+
+pub const DEFAULT_REWIND_SECONDS: usize = 10;
+
+pub struct Chip8<F: Frontend> {
+    machine: Machine,
+    frontend: F,
+    fonts: FontSet,
+    tone_playing: bool,
+    state_prefix: String,
+    state_slot: u8,
+    rewind: RewindBuffer,
+    rewinding: bool,
+    // Keys physically held on the host, restored over any state loaded from history
+    host_keys: [bool; 16],
+    // The CRC-32 and load address of the loaded ROM, which movies are tied to
+    rom: Option<(u32, u16)>,
+    seed: u32,
+    random_mode: RandomMode,
+    recorder: Option<MovieRecorder>,
+    playback: Option<Movie>,
+    playback_index: usize,
+    step_requested: bool,
+    // Frames stop running while paused, though rewinding still works
+    paused: bool,
+}
 
-use sdl2;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::render::Renderer;
-use sdl2::EventPump;
+impl<F: Frontend> fmt::Debug for Chip8<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#?}", self.machine)
+    }
+}
 
-use rand;
+impl<F: Frontend> Chip8<F> {
+    pub fn new(frontend: F) -> Chip8<F> {
+        Chip8 {
+            machine: Machine::new(),
+            frontend: frontend,
+            fonts: FontSet::default(),
+            tone_playing: false,
+            state_prefix: "rust8".to_string(),
+            state_slot: 0,
+            rewind: RewindBuffer::new(DEFAULT_REWIND_SECONDS * FRAMES_PER_SECOND as usize),
+            rewinding: false,
+            host_keys: [false; 16],
+            rom: None,
+            seed: 0,
+            random_mode: RandomMode::default(),
+            recorder: None,
+            playback: None,
+            playback_index: 0,
+            step_requested: false,
+            paused: false,
+        }
+    }
 
-use time::PreciseTime;
+    pub fn set_key_wait_policy(&mut self, policy: KeyWaitPolicy) {
+        self.machine.set_key_wait_policy(policy);
+    }
 
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.machine.set_quirks(quirks);
+    }
 
-const FRAMES_PER_SECOND: i64 = 4000;
-const SKIP_TICKS: i64 = 1000 / FRAMES_PER_SECOND;
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        self.machine.set_instructions_per_frame(instructions_per_frame);
+    }
 
-pub struct Chip8<'a> {
-    reg: Registers,
-    mem: Memory,
-    keys: Keypad,
-    sdl_event_pump: EventPump,
-    window: Renderer<'a>,
-    display: [[bool; 32]; 64],
-    display_updated: bool,
-    _next_step: bool,
-}
+    pub fn set_random(&mut self, mode: RandomMode, seed: u32) {
+        self.machine.set_random(mode, seed);
+        self.random_mode = mode;
+        self.seed = seed;
+    }
 
-impl<'a> fmt::Debug for Chip8<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#?}{:#?}{:#?}", self.reg, self.mem, self.keys)
+    // Takes effect when the display is initialised
+    pub fn set_fonts(&mut self, fonts: FontSet) {
+        self.fonts = fonts;
     }
-}
 
-impl<'a> Chip8<'a> {
-    pub fn new() -> Chip8<'a> {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    pub fn enable_schip(&mut self) {
+        self.machine.enable_schip();
+    }
 
-        let new_window = video_subsystem.window("Rust8", 640, 320)
-                                        .position_centered()
-                                        .opengl()
-                                        .build()
-                                        .unwrap();
+    pub fn enable_xo_chip(&mut self) {
+        self.machine.enable_xo_chip();
+    }
 
-        let renderer = new_window.renderer().build().unwrap();
+    // Save state slots are stored next to the ROM as `<prefix>.state<N>`
+    pub fn set_state_prefix(&mut self, prefix: &str) {
+        self.state_prefix = prefix.to_string();
+    }
 
-        Chip8 {
-            reg: Registers::new(),
-            mem: Memory::default(),
-            keys: Keypad::default(),
-            sdl_event_pump: sdl_context.event_pump().unwrap(),
-            window: renderer,
-            display: [[false; 32]; 64],
-            display_updated: false,
-            _next_step: false,
-        }
+    // Zero seconds disables rewinding and the per-frame snapshots it needs
+    pub fn set_rewind_seconds(&mut self, seconds: usize) {
+        self.rewind = RewindBuffer::new(seconds * FRAMES_PER_SECOND as usize);
     }
 
-    pub fn init_display(&mut self) {
-        self.mem.load_fonts();
+    // Writes a trace of executed instructions to `path`
+    pub fn trace_to(&mut self, path: &Path, filter: TraceFilter) -> Result<(), EmuError> {
+        let tracer = try!(Tracer::create(path, filter));
+        self.machine.set_tracer(tracer);
+        Ok(())
+    }
 
-        self.window.set_draw_color(Color::RGB(0, 0, 0));
-        self.window.clear();
-        self.window.present();
-        self.window.set_draw_color(Color::RGB(255, 255, 255));
+    // Records keypad changes from here on, under a header describing the current
+    // settings. Call this once the ROM and fonts are loaded.
+    pub fn record_movie(&mut self, path: &Path) -> Result<(), EmuError> {
+        let (rom_crc32, load_addr) = match self.rom {
+            Some(rom) => rom,
+            None => return Err(EmuError::InvalidMovie("no ROM is loaded".to_string())),
+        };
+
+        let header = MovieHeader {
+            rom_crc32: rom_crc32,
+            load_addr: load_addr,
+            font_crc32: self.fonts.crc32(),
+            seed: self.seed,
+            random_mode: self.random_mode,
+            key_wait_policy: self.machine.key_wait_policy(),
+            instructions_per_frame: self.machine.instructions_per_frame(),
+            schip: self.machine.schip(),
+            xo_chip: self.machine.xo_chip(),
+            quirks: self.machine.quirks(),
+        };
+        self.recorder = Some(try!(MovieRecorder::create(path, &header)));
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        let mut quit = false;
-        let mut start_time = PreciseTime::now();
-        let mut diff;
+    // Applies the movie's settings and replaces the host's keypad input with its events.
+    // Call this before loading the ROM and fonts, which are then checked against the movie.
+    // A platform can't be switched off again, so a machine already set up for one the
+    // movie wasn't recorded on is rejected.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), EmuError> {
+        let header = movie.header;
+        if self.machine.xo_chip() && !header.xo_chip {
+            return Err(EmuError::InvalidMovie("recorded without XO-CHIP, but it is enabled"
+                                                  .to_string()));
+        }
+        if self.machine.schip() && !header.schip {
+            return Err(EmuError::InvalidMovie("recorded without SUPER-CHIP, but it is enabled"
+                                                  .to_string()));
+        }
+
+        self.set_quirks(header.quirks);
+        self.set_key_wait_policy(header.key_wait_policy);
+        self.set_instructions_per_frame(header.instructions_per_frame);
+        self.set_random(header.random_mode, header.seed);
+        if header.schip {
+            self.enable_schip();
+        }
+        if header.xo_chip {
+            self.enable_xo_chip();
+        }
 
-        'running: loop {
-            let end_time = PreciseTime::now();
-            diff = start_time.to(end_time).num_milliseconds();
+        self.playback = Some(movie);
+        self.playback_index = 0;
+        Ok(())
+    }
+
+    pub fn save_state(&self, path: &str) -> Result<(), EmuError> {
+        let mut file = try!(File::create(path));
+        try!(file.write_all(&self.machine.save_state()));
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, path: &str) -> Result<(), EmuError> {
+        let mut file = try!(File::open(path));
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+        try!(self.machine.load_state(&data));
+        self.restore_host_keys();
 
-            self.cpu_cycle();
+        self.render();
+        self.update_tone();
+        Ok(())
+    }
 
+    pub fn init_display(&mut self) -> Result<(), EmuError> {
+        let font_crc32 = self.fonts.crc32();
+        if let Some(ref movie) = self.playback {
+            if movie.header.font_crc32 != font_crc32 {
+                return Err(EmuError::InvalidMovie(format!("recorded with fonts with CRC-32 {:08x}, \
+                                                           but these fonts' is {:08x}",
+                                                          movie.header.font_crc32,
+                                                          font_crc32)));
+            }
+        }
+
+        try!(self.machine.load_fonts(&self.fonts));
+        self.render();
+        Ok(())
+    }
 
-            quit = self.handle_input();
+    pub fn run(&mut self) -> Result<(), EmuError> {
+        let mut next_frame = time::precise_time_ns();
+        self.rewind.push(self.machine.save_state());
 
-            if quit == true {
+        'running: loop {
+            if self.handle_input() {
                 break 'running;
             }
 
-            if diff >= SKIP_TICKS {
-                start_time = end_time;
-                let delay_timer_value = self.reg.read_delay_timer();
-                if delay_timer_value > 0 {
-                    self.reg.write_delay_timer(delay_timer_value - 1);
+            if self.rewinding {
+                try!(self.rewind_frame());
+            } else if !self.paused {
+                if let Err(e) = self.machine.run_frame() {
+                    self.frontend.stop_tone();
+                    return Err(e);
                 }
+                self.rewind.push(self.machine.save_state());
+            }
+            if !self.paused {
+                self.update_tone();
+            }
 
-                let sound_timer_value = self.reg.read_sound_timer();
-                if sound_timer_value > 0 {
-                    // TODO: actually output a beep or something
-                    println!("BEEP!");
-                    self.reg.write_sound_timer(sound_timer_value - 1);
-                }
+            if self.machine.display_updated() {
+                self.render();
+            }
+
+            if self.machine.exited() {
+                break 'running;
+            }
+
+            next_frame += NANOS_PER_FRAME;
+            let now = time::precise_time_ns();
+            if next_frame > now {
+                thread::sleep(Duration::new(0, (next_frame - now) as u32));
+            } else if now - next_frame > MAX_FRAME_LAG {
+                // Resynchronise after a long stall instead of racing to catch up
+                next_frame = now;
             }
-            self.render();
         }
+
+        self.frontend.stop_tone();
+        Ok(())
     }
 
-    pub fn _run_debug(&mut self) {
-        let mut quit = false;
-        let mut start_time = PreciseTime::now();
-        let mut diff;
+    // Runs under the command-line debugger. The program starts paused; the window
+    // keeps showing the current frame while commands are typed.
+    pub fn run_debugger(&mut self) -> Result<(), EmuError> {
+        let commands = debugger::read_commands();
+        let mut running: Option<RunMode> = None;
+        let mut next_frame = time::precise_time_ns();
+
+        println!("{}", debugger::HELP);
+        debugger::print_location(&self.machine);
+        debugger::prompt();
 
         'running: loop {
-            let end_time = PreciseTime::now();
-            diff = start_time.to(end_time).num_milliseconds();
+            if self.handle_input() {
+                break 'running;
+            }
 
-            while !self._next_step {
-                quit = self.handle_input();
-                if quit == true {
-                    break 'running;
+            if self.step_requested {
+                self.step_requested = false;
+                if running.is_none() {
+                    // Like the step command, a breakpoint or an error is reported and the
+                    // session goes on
+                    if let Some(hit) = self.machine.next_breakpoint_hit() {
+                        debugger::print_hit(&self.machine, &hit);
+                    } else if let Err(e) = self.machine.step_in_frame() {
+                        println!("{}", e);
+                    }
+                    debugger::print_location(&self.machine);
+                    debugger::prompt();
                 }
             }
-            self._next_step = false;
 
-            self.cpu_cycle();
+            match commands.try_recv() {
+                Ok(_) if running.is_some() => {
+                    // Any line pauses a running program
+                    running = None;
+                    println!("Paused");
+                    debugger::print_location(&self.machine);
+                    debugger::prompt();
+                }
+                Ok(line) => {
+                    match debugger::execute(&mut self.machine, &line) {
+                        Ok(Action::Prompt) => debugger::prompt(),
+                        Ok(Action::Run(mode)) => running = Some(mode),
+                        Ok(Action::Quit) => break 'running,
+                        Err(e) => {
+                            println!("{}", e);
+                            debugger::prompt();
+                        }
+                    }
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => break 'running,
+            }
+
+            if let Some(mode) = running {
+                let stopped = match self.machine.run_frame_until(|m| mode.should_stop(m)) {
+                    Ok(FrameOutcome::Completed) => false,
+                    Ok(FrameOutcome::Breakpoint(hit)) => {
+                        debugger::print_hit(&self.machine, &hit);
+                        true
+                    }
+                    Ok(FrameOutcome::Stopped) => true,
+                    Err(e) => {
+                        println!("{}", e);
+                        true
+                    }
+                };
+
+                if stopped || self.machine.exited() {
+                    if self.machine.exited() {
+                        println!("Program has exited");
+                    }
+                    running = None;
+                    debugger::print_location(&self.machine);
+                    debugger::prompt();
+                }
+            }
 
-            if self.display_updated {
+            self.update_tone();
+            if self.machine.display_updated() {
                 self.render();
             }
 
-            println!("{:?}", self);
+            next_frame += NANOS_PER_FRAME;
+            let now = time::precise_time_ns();
+            if next_frame > now {
+                thread::sleep(Duration::new(0, (next_frame - now) as u32));
+            } else {
+                next_frame = now;
+            }
+        }
+
+        self.frontend.stop_tone();
+        Ok(())
+    }
 
-            quit = self.handle_input();
+    // Serves the GDB remote protocol on a local port. Emulation waits for a debugger to
+    // connect and starts out stopped; once it detaches the program carries on as normal.
+    pub fn run_gdb(&mut self, port: u16) -> Result<(), EmuError> {
+        let mut stub = try!(GdbStub::listen(port));
+        let mut running = false;
+        let mut next_frame = time::precise_time_ns();
 
-            if quit == true {
+        'running: loop {
+            if self.handle_input() {
+                let _ = stub.report_exit();
                 break 'running;
             }
 
-            if diff >= SKIP_TICKS {
-                start_time = end_time;
-
+            loop {
+                let request = match stub.poll() {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("GDB disconnected: {}", e);
+                        stub.remove_all_breakpoints(&mut self.machine);
+                        return self.run();
+                    }
+                };
 
-                let delay_timer_value = self.reg.read_delay_timer();
-                if delay_timer_value > 0 {
-                    self.reg.write_delay_timer(delay_timer_value - 1);
+                let result = match request {
+                    Request::Interrupt if running => {
+                        running = false;
+                        stub.report_interrupt().map(|_| Resume::Stay)
+                    }
+                    Request::Interrupt => Ok(Resume::Stay),
+                    Request::Packet(packet) => stub.handle(&mut self.machine, &packet),
+                };
+
+                match result {
+                    Ok(Resume::Stay) => {}
+                    Ok(Resume::Continue) => running = true,
+                    Ok(Resume::Kill) => break 'running,
+                    Ok(Resume::Detach) | Err(_) => {
+                        println!("GDB detached");
+                        stub.remove_all_breakpoints(&mut self.machine);
+                        return self.run();
+                    }
                 }
+            }
 
-                let sound_timer_value = self.reg.read_sound_timer();
-                if sound_timer_value > 0 {
-                    // TODO: actually output a beep or something
-                    println!("BEEP!");
-                    self.reg.write_sound_timer(sound_timer_value - 1);
+            if running {
+                let reported = match self.machine.run_frame() {
+                    Ok(FrameOutcome::Breakpoint(hit)) => Some(stub.report_hit(&hit)),
+                    Ok(_) if self.machine.exited() => Some(stub.report_exit()),
+                    Ok(_) => None,
+                    Err(e) => {
+                        println!("Emulation stopped: {}", e);
+                        Some(stub.report_error())
+                    }
+                };
+                if reported.is_some() {
+                    running = false;
                 }
             }
+
+            self.update_tone();
+            if self.machine.display_updated() {
+                self.render();
+            }
+
+            next_frame += NANOS_PER_FRAME;
+            let now = time::precise_time_ns();
+            if next_frame > now {
+                thread::sleep(Duration::new(0, (next_frame - now) as u32));
+            } else {
+                next_frame = now;
+            }
         }
+
+        self.frontend.stop_tone();
+        Ok(())
     }
 
-    pub fn store_program_data(&mut self, rom: File) {
-        self.mem.store_program_data(rom);
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), EmuError> {
+        let rom_crc32 = rom.crc32();
+        if let Some(ref movie) = self.playback {
+            if movie.header.rom_crc32 != rom_crc32 {
+                return Err(EmuError::InvalidMovie(format!("recorded with a ROM with CRC-32 {:08x}, \
+                                                           but this ROM's is {:08x}",
+                                                          movie.header.rom_crc32,
+                                                          rom_crc32)));
+            }
+            if movie.header.load_addr != rom.load_addr() {
+                return Err(EmuError::InvalidMovie(format!("recorded with the ROM at {:#05x}, but \
+                                                           it is loaded at {:#05x}",
+                                                          movie.header.load_addr,
+                                                          rom.load_addr())));
+            }
+        }
+
+        try!(self.machine.load_rom(rom));
+        self.rom = Some((rom_crc32, rom.load_addr()));
+        Ok(())
     }
 
     pub fn _debug_pong_rom(&self) {
-        self.mem._display_pong_rom();
+        self.machine._debug_pong_rom();
     }
 
     pub fn _debug_font_data(&self) {
-        self.mem._display_font_data();
+        self.machine._debug_font_data();
     }
 
-    fn cpu_cycle(&mut self) {
-        let instruction = self.read_word();
-        self.process_instruction(instruction);
+    fn render(&mut self) {
+        self.frontend.present(self.machine.framebuffer());
+        self.machine.clear_display_updated();
     }
 
-    fn render(&mut self) {
-        let mut fg_rect_vec: Vec<Rect> = Vec::new();
-        let mut bg_rect_vec: Vec<Rect> = Vec::new();
-
-        for x in 0..64 {
-            for y in 0..32 {
-                // println!("Loading display byte at {},{}", x, y);
-                let nibble = self.display[x][y];
-                if nibble {
-                    fg_rect_vec.push(Rect::new_unwrap((x * 10) as i32, (y * 10) as i32, 10, 10));
-                } else {
-                    bg_rect_vec.push(Rect::new_unwrap((x * 10) as i32, (y * 10) as i32, 10, 10));
+    fn update_tone(&mut self) {
+        if self.machine.audio_updated() {
+            // A loaded state without a pattern has to clear the one playing
+            self.frontend.set_audio_pattern(self.machine.audio_pattern(),
+                                            self.machine.audio_pitch());
+            self.machine.clear_audio_updated();
+        }
+
+        let sound_active = self.machine.sound_active();
+        if sound_active && !self.tone_playing {
+            self.frontend.start_tone();
+        } else if !sound_active && self.tone_playing {
+            self.frontend.stop_tone();
+        }
+        self.tone_playing = sound_active;
+    }
+
+    // Steps one frame back through the history, staying on the oldest frame once it runs out
+    fn rewind_frame(&mut self) -> Result<(), EmuError> {
+        if let Some(state) = self.rewind.pop() {
+            try!(self.machine.load_state(state));
+            self.restore_host_keys();
+        }
+        Ok(())
+    }
+
+    fn restore_host_keys(&mut self) {
+        for key in 0..16 {
+            self.machine.set_key(key as u8, self.host_keys[key]);
+        }
+    }
+
+    fn movie_active(&self) -> bool {
+        self.recorder.is_some() || self.playback.is_some()
+    }
+
+    // While a movie plays, its events for the current frame stand in for the host's keys
+    fn movie_input(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let mut finished = false;
+
+        if let Some(ref movie) = self.playback {
+            while let Some(event) = movie.events.get(self.playback_index) {
+                if event.frame > self.machine.frames() {
+                    break;
                 }
+                events.push(if event.pressed {
+                    InputEvent::KeyDown(event.key)
+                } else {
+                    InputEvent::KeyUp(event.key)
+                });
+                self.playback_index += 1;
             }
+            finished = self.playback_index == movie.events.len();
         }
 
-        self.window.set_draw_color(Color::RGB(0, 0, 0));
-
-        for r in bg_rect_vec {
-            self.window.fill_rect(r);
+        if finished {
+            println!("Movie playback finished at frame {}", self.machine.frames());
+            self.playback = None;
         }
 
-        self.window.set_draw_color(Color::RGB(255, 255, 255));
+        events
+    }
+
+    fn record_key(&mut self, key: u8, pressed: bool) {
+        let event = MovieEvent {
+            frame: self.machine.frames(),
+            key: key,
+            pressed: pressed,
+        };
+
+        let failed = match self.recorder {
+            Some(ref mut recorder) => {
+                match recorder.record(&event) {
+                    Ok(()) => false,
+                    Err(e) => {
+                        println!("Stopped recording movie: {}", e);
+                        true
+                    }
+                }
+            }
+            None => false,
+        };
+
+        if failed {
+            self.recorder = None;
+        }
+    }
 
-        for r in fg_rect_vec {
-            self.window.fill_rect(r);
+    fn set_host_key(&mut self, key: u8, pressed: bool) {
+        // Held keys repeat, but only actual changes go into a movie
+        if self.host_keys[key as usize] != pressed {
+            self.record_key(key, pressed);
         }
+        self.host_keys[key as usize] = pressed;
+        self.machine.set_key(key, pressed);
+    }
 
-        self.window.present();
-        self.display_updated = false;
+    fn state_slot_path(&self) -> String {
+        format!("{}.state{}", self.state_prefix, self.state_slot)
     }
 
     fn handle_input(&mut self) -> bool {
+        let mut events = self.frontend.poll_input();
+        if self.playback.is_some() {
+            events.retain(|event| match *event {
+                InputEvent::KeyDown(_) | InputEvent::KeyUp(_) => false,
+                _ => true,
+            });
+            events.extend(self.movie_input());
+        }
 
-        for event in self.sdl_event_pump.poll_iter() {
+        for event in events {
             match event {
-                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), .. } => {
+                InputEvent::Quit => {
                     return true
                 }
-                Event::KeyDown {keycode: Some(Keycode::Num1), ..} => {
-                    self.keys.keys[1] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::Num2), ..} => {
-                    self.keys.keys[2] = true;
+                InputEvent::KeyDown(key) => {
+                    self.set_host_key(key, true);
                 }
-                Event::KeyDown {keycode: Some(Keycode::Num3), ..} => {
-                    self.keys.keys[3] = true;
+                InputEvent::KeyUp(key) => {
+                    self.set_host_key(key, false);
                 }
-                Event::KeyDown {keycode: Some(Keycode::Num4), ..} => {
-                    self.keys.keys[12] = true;
+                InputEvent::Step => {
+                    self.step_requested = true;
                 }
-                Event::KeyDown {keycode: Some(Keycode::Q), ..} => {
-                    self.keys.keys[4] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::W), ..} => {
-                    self.keys.keys[5] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::E), ..} => {
-                    self.keys.keys[6] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::R), ..} => {
-                    self.keys.keys[13] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::A), ..} => {
-                    self.keys.keys[7] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::S), ..} => {
-                    self.keys.keys[8] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::D), ..} => {
-                    self.keys.keys[9] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::F), ..} => {
-                    self.keys.keys[14] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::Z), ..} => {
-                    self.keys.keys[10] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::X), ..} => {
-                    self.keys.keys[0] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::C), ..} => {
-                    self.keys.keys[11] = true;
-                }
-                Event::KeyDown {keycode: Some(Keycode::V), ..} => {
-                    self.keys.keys[15] = true;
-                }
-                Event::KeyUp {keycode: Some(Keycode::Num1), ..} => {
-                    self.keys.keys[1] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::Num2), ..} => {
-                    self.keys.keys[2] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::Num3), ..} => {
-                    self.keys.keys[3] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::Num4), ..} => {
-                    self.keys.keys[12] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::Q), ..} => {
-                    self.keys.keys[4] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::W), ..} => {
-                    self.keys.keys[5] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::E), ..} => {
-                    self.keys.keys[6] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::R), ..} => {
-                    self.keys.keys[13] = false;
-                }
-                Event::KeyUp {keycode: Some(Keycode::A), ..} => {
-                    self.keys.keys[7] = false;
+                InputEvent::Pause => {
+                    self.paused = !self.paused;
+                    if self.paused {
+                        self.frontend.stop_tone();
+                        self.tone_playing = false;
+                        println!("Paused");
+                    } else {
+                        println!("Resumed");
+                    }
                 }
-                Event::KeyUp {keycode: Some(Keycode::S), ..} => {
-                    self.keys.keys[8] = false;
+                InputEvent::DumpMemory => {
+                    match self.machine._dump_mem_to_disk() {
+                        Ok(()) => println!("Dumped memory to disk."),
+                        Err(e) => println!("Could not dump memory: {}", e),
+                    }
                 }
-                Event::KeyUp {keycode: Some(Keycode::D), ..} => {
-                    self.keys.keys[9] = false;
+                InputEvent::SaveState => {
+                    let path = self.state_slot_path();
+                    match self.save_state(&path) {
+                        Ok(()) => println!("Saved state to slot {} ({})", self.state_slot, path),
+                        Err(e) => println!("Could not save state: {}", e),
+                    }
                 }
-                Event::KeyUp {keycode: Some(Keycode::F), ..} => {
-                    self.keys.keys[14] = false;
+                InputEvent::LoadState if self.movie_active() => {
+                    println!("Loading states is disabled while a movie is active");
                 }
-                Event::KeyUp {keycode: Some(Keycode::Z), ..} => {
-                    self.keys.keys[10] = false;
+                InputEvent::LoadState => {
+                    let path = self.state_slot_path();
+                    match self.load_state(&path) {
+                        Ok(()) => println!("Loaded state from slot {} ({})", self.state_slot, path),
+                        Err(e) => println!("Could not load state: {}", e),
+                    }
                 }
-                Event::KeyUp {keycode: Some(Keycode::X), ..} => {
-                    self.keys.keys[0] = false;
+                InputEvent::NextStateSlot => {
+                    self.state_slot = (self.state_slot + 1) % STATE_SLOTS;
+                    println!("Selected state slot {}", self.state_slot);
                 }
-                Event::KeyUp {keycode: Some(Keycode::C), ..} => {
-                    self.keys.keys[11] = false;
+                InputEvent::PreviousStateSlot => {
+                    self.state_slot = (self.state_slot + STATE_SLOTS - 1) % STATE_SLOTS;
+                    println!("Selected state slot {}", self.state_slot);
                 }
-                Event::KeyUp {keycode: Some(Keycode::V), ..} => {
-                    self.keys.keys[15] = false;
+                InputEvent::RewindStart if self.movie_active() => {
+                    println!("Rewinding is disabled while a movie is active");
                 }
-                Event::KeyDown {keycode: Some(Keycode::K), ..} => {
-                    self._next_step = true;
+                InputEvent::RewindStart => {
+                    self.rewinding = true;
                 }
-                Event::KeyDown {keycode: Some(Keycode::M), ..} => {
-                    self.mem._dump_mem_to_disk();
+                InputEvent::RewindStop => {
+                    self.rewinding = false;
                 }
-                _ => {}
             }
         }
 
         false
     }
+}
 
-    fn read_word(&mut self) -> u16 {
-        let instruction_high_order = (self.mem.read_byte(self.reg.read_pc()) as u16) << 8;
-        let instruction_low_order = self.mem.read_byte(self.reg.read_pc() + 1) as u16;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::display::Display;
 
-        let instruction = instruction_high_order | instruction_low_order;
+    use std::env;
+    use std::fs;
 
-        self.reg.increment_pc();
-        instruction
-    }
+    // Runs without a window, sound or host input, so only a movie can press keys
+    struct NullFrontend;
 
-    fn process_instruction(&mut self, instruction: u16) {
-        let op_type: u8 = ((instruction >> 12) & 0xff) as u8;
+    impl Frontend for NullFrontend {
+        fn present(&mut self, _: &Display) {}
 
-        match op_type {
-            0x0 => {
-                // we will ignore the 0nnn opcode used for jumping to machine code routines
-                let operation = instruction & 0x00ff;
-                if operation == 0xe0 {
-                    println!("PC: {:#x}    |    Opcode: {:#x}      |    cls",
-                             self.reg.read_pc() - 2,
-                             instruction);
-                    for x in 0..64 {
-                        for y in 0..32 {
-                            self.display[x][y] = false;
-                        }
-                    }
-                    self.display_updated = true;
-                } else if operation == 0xee {
-                    println!("PC: {:#x}    |    Opcode: {:#x}      |    ret",
-                             self.reg.read_pc() - 2,
-                             instruction);
-                    self.reg.return_from_subroutine();
-                }
-            }
-            0x1 => {
-                let jump_addr = instruction & 0x0fff;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    jmp {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         jump_addr);
-                self.reg.jump_to_address(jump_addr, JumpType::NORMAL);
-            }
-            0x2 => {
-                let subroutine_addr = instruction & 0x0fff;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    call {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         subroutine_addr);
-                self.reg.jump_to_address(subroutine_addr, JumpType::SUBROUTINE);
-            }
-            0x3 => {
-                let target_reg = ((instruction & 0x0f00) >> 8) as u8;
-                let comparison_byte = (instruction & 0x00ff) as u8;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    se V{} {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         target_reg,
-                         comparison_byte);
-                if self.reg.read_register(target_reg) == comparison_byte {
-                    self.reg.increment_pc();
-                }
-            }
-            0x4 => {
-                let target_reg = ((instruction & 0x0f00) >> 8) as u8;
-                let comparison_byte = (instruction & 0x00ff) as u8;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    sne V{} {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         target_reg,
-                         comparison_byte);
-                if self.reg.read_register(target_reg) != comparison_byte {
-                    self.reg.increment_pc();
-                }
-            }
-            0x5 => {
-                let reg_one = ((instruction & 0x0f00) >> 8) as u8;
-                let reg_two = ((instruction & 0x00f0) >> 4) as u8;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    se V{} V{}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         reg_one,
-                         reg_two);
-                if self.reg.read_register(reg_one) == self.reg.read_register(reg_two) {
-                    self.reg.increment_pc();
-                }
-            }
-            0x6 => {
-                let target_reg = ((instruction >> 8) & 0x0f) as u8;
-                let data_value = (instruction & 0x00ff) as u8;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    ld V{} {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         target_reg,
-                         data_value);
-                self.reg.write_register(target_reg, data_value);
-            }
-            0x7 => {
-                let target_reg = ((instruction >> 8) & 0x0f) as u8;
-                let immediate_value = (instruction & 0x00ff) as u8;
-                let reg_value = self.reg.read_register(target_reg);
-                let data_value = immediate_value.wrapping_add(reg_value);
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    add V{} {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         target_reg,
-                         immediate_value);
-                self.reg.write_register(target_reg, data_value);
-            }
-            0x8 => {
-                let reg_one = ((instruction >> 8) & 0x0f) as u8;
-                let reg_two = ((instruction >> 4) & 0x0f) as u8;
-                let operation = (instruction & 0x000f) as u8;
-                match operation {
-                    0 => {
-                        let data_value = self.reg.read_register(reg_two);
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, data_value);
-                    }
-                    1 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
-                        let data_value = reg_one_value | reg_two_value;
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    or V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, data_value);
-                    }
-                    2 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
-                        let data_value = reg_one_value & reg_two_value;
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    and V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, data_value);
-                    }
-                    3 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
-                        if reg_two_value > reg_one_value {
-                            self.reg.write_register(0x0f, 0x01);
-                        }
-                        let data_value = reg_two_value - reg_one_value;
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    xor V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, data_value);
-                    }
-                    4 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
+        fn poll_input(&mut self) -> Vec<InputEvent> {
+            Vec::new()
+        }
 
-                        let mut result: u32 = (reg_one_value as u32) + (reg_two_value as u32);
+        fn start_tone(&mut self) {}
 
-                        if result > 255 {
-                            self.reg.set_vf();
-                        } else {
-                            self.reg.clear_vf();
-                        }
+        fn stop_tone(&mut self) {}
 
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    add V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, result as u8);
-                    }
-                    5 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
-
-                        if reg_one_value > reg_two_value {
-                            self.reg.set_vf();
-                        } else {
-                            self.reg.clear_vf();
-                        }
+        fn set_audio_pattern(&mut self, _: Option<&[u8; 16]>, _: u8) {}
+    }
 
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    sub V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, reg_one_value.wrapping_sub(reg_two_value));
-                    }
-                    6 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    shr V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-
-                        if (reg_one_value & 1) == 1 {
-                            self.reg.set_vf();
-                        } else {
-                            self.reg.clear_vf();
-                        }
+    // Waits for a key into V0, then exits
+    const WAIT_THEN_EXIT: [u8; 4] = [0xf0, 0x0a, 0x00, 0xfd];
+
+    fn movie_for(rom: &Rom, events: Vec<MovieEvent>) -> Movie {
+        Movie {
+            header: MovieHeader {
+                rom_crc32: rom.crc32(),
+                load_addr: rom.load_addr(),
+                font_crc32: FontSet::default().crc32(),
+                seed: 1,
+                random_mode: RandomMode::default(),
+                key_wait_policy: KeyWaitPolicy::default(),
+                instructions_per_frame: 10,
+                schip: true,
+                xo_chip: false,
+                quirks: Quirks::default(),
+            },
+            events: events,
+        }
+    }
 
-                        self.reg.write_register(reg_one, reg_one_value >> 1);
-                    }
-                    7 => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        let reg_two_value = self.reg.read_register(reg_two);
-
-                        if reg_two_value > reg_one_value {
-                            self.reg.set_vf();
-                        } else {
-                            self.reg.clear_vf();
-                        }
+    fn key_event(frame: u32, key: u8, pressed: bool) -> MovieEvent {
+        MovieEvent {
+            frame: frame,
+            key: key,
+            pressed: pressed,
+        }
+    }
 
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    subn V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-                        self.reg.write_register(reg_one, reg_two_value.wrapping_sub(reg_one_value));
-                    }
-                    0xe => {
-                        let reg_one_value = self.reg.read_register(reg_one);
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    shl V{} V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 reg_one,
-                                 reg_two);
-
-                        if ((reg_one_value >> 7) & 1) == 1 {
-                            self.reg.set_vf();
-                        } else {
-                            self.reg.clear_vf();
-                        }
+    #[test]
+    fn play_movie_presses_keys_on_their_frames() {
+        let rom = Rom::from_bytes(&WAIT_THEN_EXIT);
+        let mut chip8 = Chip8::new(NullFrontend);
+        let events = vec![key_event(3, 0x5, true), key_event(5, 0x5, false)];
+        chip8.play_movie(movie_for(&rom, events)).unwrap();
+        chip8.load_rom(&rom).unwrap();
+        chip8.init_display().unwrap();
+
+        chip8.run().unwrap();
+
+        assert!(chip8.machine.exited());
+        assert_eq!(chip8.machine.registers().read_register(0), 5);
+        // The key came up at the start of frame 5, which ended the wait and then exited
+        assert_eq!(chip8.machine.frames(), 6);
+        assert!(chip8.playback.is_none());
+    }
 
-                        self.reg.write_register(reg_one, reg_one_value << 1);
-                    }
-                    _ => panic!("Unrecognized opcode: {:#x}", instruction),
-                }
-            }
-            0x9 => {
-                let reg_one = ((instruction & 0x0f00) >> 8) as u8;
-                let reg_two = ((instruction & 0x00f0) >> 4) as u8;
-                let reg_one_value = self.reg.read_register(reg_one);
-                let reg_two_value = self.reg.read_register(reg_two);
-
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    sne V{} V{}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         reg_one,
-                         reg_two);
-
-                if reg_one_value != reg_two_value {
-                    self.reg.increment_pc();
-                }
-            }
-            0xa => {
-                let data_value = instruction & 0x0fff;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    ld i {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         data_value);
-                self.reg.write_register_i(data_value);
-            }
-            0xb => {
-                let initial_addr = instruction & 0x0fff;
-                let offset = self.reg.read_register(0) as u16;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    jp V0 {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         initial_addr + offset);
-                self.reg.jump_to_address(initial_addr + offset, JumpType::NORMAL);
-            }
-            0xc => {
-                let target_reg = ((instruction & 0x0f00) >> 8) as u8;
-                let combination_byte = (instruction & 0x00ff) as u8;
-                let rand_num: u8 = rand::random();
-
-                self.reg.write_register(target_reg, (combination_byte & rand_num));
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    rnd V{} {:#x}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         target_reg,
-                         combination_byte);
-                println!("             |   rand_num: {:#x}   |    final byte: {:#x}",
-                         rand_num,
-                         combination_byte & rand_num);
-            }
-            0xd => {
-                let reg_one = ((instruction & 0x0F00) >> 8) as u8;
-                let reg_two = ((instruction & 0x00F0) >> 4) as u8;
-                let num_bytes = (instruction & 0x000F) as u8;
-                println!("PC: {:#x}    |    Opcode: {:#x}    |    drw V{} V{} {}",
-                         self.reg.read_pc() - 2,
-                         instruction,
-                         reg_one,
-                         reg_two,
-                         num_bytes);
-
-                let sprite_x = self.reg.read_register(reg_one);
-                let sprite_y = self.reg.read_register(reg_two);
-                println!("Sprite X: {}  |  Sprite Y: {}", sprite_x, sprite_y);
-                let mut bit_vec: Vec<u8> = Vec::new();
-                for i in 0..num_bytes {
-                    bit_vec.push(self.mem.read_byte(self.reg.read_register_i() + (i as u16)));
-                }
+    #[test]
+    fn play_movie_rejects_a_different_rom() {
+        let rom = Rom::from_bytes(&WAIT_THEN_EXIT);
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.play_movie(movie_for(&rom, Vec::new())).unwrap();
 
-                println!("Glyph:");
-                for byte in bit_vec.clone() {
-                    println!("{:#8b}", byte);
-                }
-                println!("");
+        assert!(chip8.load_rom(&Rom::from_bytes(&[0x00, 0xfd])).is_err());
+    }
 
-                self.reg.clear_vf();
+    #[test]
+    fn play_movie_rejects_a_different_load_address() {
+        let rom = Rom::from_bytes(&WAIT_THEN_EXIT);
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.play_movie(movie_for(&rom, Vec::new())).unwrap();
 
-                let mut y_index = sprite_y as usize;
-                let mut x_value = sprite_x as usize;
-                for byte in bit_vec.clone() {
+        let mut moved = Rom::from_bytes(&WAIT_THEN_EXIT);
+        moved.set_load_addr(0x600);
+        assert!(chip8.load_rom(&moved).is_err());
+    }
 
-                    for i in 0..8 {
-                        let mut x_index = x_value + (7 - i);
-                        if x_index > 63 {
-                            x_index = 69 - x_value;
-                        }
-                        if y_index > 31 {
-                            y_index = y_index - 32;
-                        }
+    #[test]
+    fn play_movie_rejects_different_fonts() {
+        let rom = Rom::from_bytes(&WAIT_THEN_EXIT);
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.play_movie(movie_for(&rom, Vec::new())).unwrap();
+        chip8.load_rom(&rom).unwrap();
+
+        let mut fonts = FontSet::default();
+        fonts.set_small("dream6800").unwrap();
+        chip8.set_fonts(fonts);
+        assert!(chip8.init_display().is_err());
+    }
 
-                        let mut bit_state: bool = false;
-                        if (byte >> i) & 1 == 1 {
-                            bit_state = true;
-                        }
 
-                        if bit_state != self.display[x_index][y_index] {
-                            self.display[x_index][y_index] = true;
-                        } else {
-                            if self.display[x_index][y_index] == true {
-                                self.reg.set_vf();
-                            }
+    // Switches to hi-res, waits for a key into V0, then exits
+    const HIRES_WAIT_THEN_EXIT: [u8; 6] = [0x00, 0xff, 0xf0, 0x0a, 0x00, 0xfd];
 
-                            self.display[x_index][y_index] = false;
-                        }
-                    }
+    #[test]
+    fn play_movie_applies_the_recorded_platform_and_quirks() {
+        let rom = Rom::from_bytes(&HIRES_WAIT_THEN_EXIT);
+        let mut movie = movie_for(&rom, vec![key_event(2, 0x7, true), key_event(4, 0x7, false)]);
+        movie.header.quirks.set("shift_uses_vy", true).unwrap();
+        let quirks = movie.header.quirks;
 
-                    y_index += 1;
-                }
+        // Set up as plain CHIP-8, which would reject 00FF
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.play_movie(movie).unwrap();
+        chip8.load_rom(&rom).unwrap();
+        chip8.init_display().unwrap();
+        chip8.run().unwrap();
 
-                self.display_updated = true;
-            }
-            0xe => {
-                let optype = (instruction & 0x00ff) as u8;
-                let target_reg = ((instruction & 0x0f00) >> 8) as u8;
-
-                match optype {
-                    0x9e => {
-                        let key = self.reg.read_register(target_reg);
-                        if self.keys.keys[key as usize] == true {
-                            self.reg.increment_pc();
-                        }
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    skp V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 target_reg);
-                    }
-                    0xa1 => {
-                        let key = self.reg.read_register(target_reg);
-                        if self.keys.keys[key as usize] == false {
-                            self.reg.increment_pc();
-                        }
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    sknp V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 target_reg);
-                    }
-                    _ => panic!("Invalid instruction: {:#4x}", instruction),
-                }
-            }
-            0xf => {
-                let operation = (instruction & 0x00FF) as u8;
-                let register_index = ((instruction & 0x0F00) >> 8) as u8;
-
-                match operation {
-                    0x07 => {
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld V{} DT",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                        let reg_value = self.reg.read_delay_timer();
-                        self.reg.write_register(register_index, reg_value);
-                    }
-                    0x15 => {
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld DT V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                        let reg_value = self.reg.read_register(register_index);
-                        self.reg.write_delay_timer(reg_value);
-                    }
-                    0x18 => {
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld ST V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                        let reg_value = self.reg.read_register(register_index);
-                        self.reg.write_sound_timer(reg_value);
-                    }
-                    0x1e => {
-                        let reg_value = self.reg.read_register(register_index);
-                        let i_value = self.reg.read_register_i();
-
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    add I V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                        self.reg.write_register_i((reg_value as u16) + i_value);
-                    }
-                    0x29 => {
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld F V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-
-                        let reg_value = self.reg.read_register(register_index);
-                        match reg_value {
-                            0 => {
-                                self.reg.write_register_i(0x0);
-                            }
-                            1 => {
-                                self.reg.write_register_i(0x5);
-                            }
-                            2 => {
-                                self.reg.write_register_i(0xa);
-                            }
-                            3 => {
-                                self.reg.write_register_i(0xf);
-                            }
-                            4 => {
-                                self.reg.write_register_i(0x14);
-                            }
-                            5 => {
-                                self.reg.write_register_i(0x19);
-                            }
-                            6 => {
-                                self.reg.write_register_i(0x1e);
-                            }
-                            7 => {
-                                self.reg.write_register_i(0x23);
-                            }
-                            8 => {
-                                self.reg.write_register_i(0x28);
-                            }
-                            9 => {
-                                self.reg.write_register_i(0x2d);
-                            }
-                            0xa => {
-                                self.reg.write_register_i(0x32);
-                            }
-                            0xb => {
-                                self.reg.write_register_i(0x37);
-                            }
-                            0xc => {
-                                self.reg.write_register_i(0x3c);
-                            }
-                            0xd => {
-                                self.reg.write_register_i(0x41);
-                            }
-                            0xe => {
-                                self.reg.write_register_i(0x46);
-                            }
-                            0xf => {
-                                self.reg.write_register_i(0x4b);
-                            }
-                            _ => {
-                                panic!("Should never hit this statement, all cases covered.");
-                            }
-                        }
-                    }
-                    0x33 => {
-                        let mut reg_value = self.reg.read_register(register_index);
-                        let ones_digit: u8 = reg_value % 10;
-                        reg_value = reg_value / 10;
-                        let tens_digit: u8 = reg_value % 10;
-                        reg_value = reg_value / 10;
-                        let hundreds_digit: u8 = reg_value % 10;
-
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld B V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-
-                        self.mem.write_byte(self.reg.read_register_i(), hundreds_digit);
-                        self.mem.write_byte(self.reg.read_register_i() + 1, tens_digit);
-                        self.mem.write_byte(self.reg.read_register_i() + 2, ones_digit);
-                    }
-                    0x55 => {
-                        let num_reg = register_index as usize;
-                        let mut mem_addr = self.reg.read_register_i();
-                        for n in 0..num_reg {
-                            self.mem
-                                .write_byte(mem_addr + (n as u16), self.reg.read_register(n as u8));
-                        }
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld [I] V{}",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                    }
-                    0x65 => {
-                        println!("PC: {:#x}    |    Opcode: {:#x}    |    ld V{} [I]",
-                                 self.reg.read_pc() - 2,
-                                 instruction,
-                                 register_index);
-                        let mem_addr = self.reg.read_register_i();
-                        for n in 0..(register_index + 1) {
-                            let byte = self.mem.read_byte(mem_addr + (n as u16));
-                            self.reg.write_register(n as u8, byte);
-                        }
-                    }
-                    _ => {
-                        println!("Chip8 status at end time: {:#?}", self);
-                        println!("*************Unrecognized opcode!*************");
-                        panic!("PC: {:#x}    |    Opcode: {:#x}    |    various",
-                               self.reg.read_pc() - 2,
-                               instruction);
-                    }
-                }
-            }
-            _ => {
-                println!("Chip8 status at end time: {:#?}", self);
-                panic!("Unsupported op type: {:#2x}", op_type);
-            }
-        }
+        assert!(chip8.machine.schip());
+        assert_eq!(chip8.machine.quirks(), quirks);
+        assert!(chip8.machine.exited());
+        assert_eq!(chip8.machine.registers().read_register(0), 7);
     }
-}
 
-pub enum JumpType {
-    NORMAL,
-    SUBROUTINE,
+    #[test]
+    fn play_movie_rejects_a_platform_it_was_not_recorded_on() {
+        let rom = Rom::from_bytes(&WAIT_THEN_EXIT);
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.enable_xo_chip();
+
+        assert!(chip8.play_movie(movie_for(&rom, Vec::new())).is_err());
+        assert!(chip8.playback.is_none());
+    }
+
+    #[test]
+    fn record_movie_needs_a_rom() {
+        let mut chip8 = Chip8::new(NullFrontend);
+        let path = env::temp_dir().join("rust8-no-rom.movie");
+
+        assert!(chip8.record_movie(&path).is_err());
+    }
+
+    #[test]
+    fn record_movie_describes_the_loaded_rom_and_settings() {
+        let rom = Rom::from_bytes(&HIRES_WAIT_THEN_EXIT);
+        let mut quirks = Quirks::default();
+        quirks.set("clip_sprites", true).unwrap();
+
+        let mut chip8 = Chip8::new(NullFrontend);
+        chip8.enable_schip();
+        chip8.set_quirks(quirks);
+        chip8.set_random(RandomMode::CosmacVip, 42);
+        chip8.set_instructions_per_frame(15);
+        chip8.load_rom(&rom).unwrap();
+        chip8.init_display().unwrap();
+
+        let path = env::temp_dir().join("rust8-record.movie");
+        chip8.record_movie(&path).unwrap();
+        chip8.record_key(0x3, true);
+        chip8.recorder = None;
+
+        let movie = Movie::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(movie.header,
+                   MovieHeader {
+                       rom_crc32: rom.crc32(),
+                       load_addr: rom.load_addr(),
+                       font_crc32: FontSet::default().crc32(),
+                       seed: 42,
+                       random_mode: RandomMode::CosmacVip,
+                       key_wait_policy: KeyWaitPolicy::default(),
+                       instructions_per_frame: 15,
+                       schip: true,
+                       xo_chip: false,
+                       quirks: quirks,
+                   });
+        assert_eq!(movie.events, vec![key_event(0, 0x3, true)]);
+    }
 }
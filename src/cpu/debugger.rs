@@ -0,0 +1,461 @@
+use super::machine::Machine;
+use super::instruction::{decode_with_next, Instruction};
+use super::error::StepOutcome;
+use super::breakpoint::{Access, Comparison, Condition, Hit, OpcodePattern, Operand, Trigger};
+
+use number::parse_number;
+
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub const HELP: &'static str = "Commands:
+    step [n]            Run n instructions (default 1)
+    continue            Run until a breakpoint; enter any line to pause
+    until ret           Run until the current subroutine returns
+    break [addr]        Set a breakpoint, or list them without an address
+    break on <opcode>   Stop before any instruction matching a pattern like Dxyn
+    break when <cond>   Stop when a condition such as V3 == 0x10 becomes true
+    watch <addr> [len] [r|w|x]
+                        Stop before memory is read, written (default) or executed
+    ignore <n> <count>  Let breakpoint n pass its next count hits
+    delete [n]          Remove breakpoint n, or all of them
+    regs                Show the registers and timers
+    mem <addr> [len]    Dump memory (default 64 bytes)
+    set <reg> <value>   Change V0-VF, I, PC, DT or ST
+    disasm [addr] [n]   Disassemble n instructions (default: 10 from PC)
+    stack               Show the call stack
+    quit                Stop emulation
+
+Breakpoints and watchpoints take a trailing 'if <cond>' to only stop while it
+holds. Conditions compare V0-VF, I, PC, SP, DT, ST or a memory byte [addr]
+with ==, !=, <, <=, > or >=.";
+
+// What the run loop should do after a command
+pub enum Action {
+    Prompt,
+    Run(RunMode),
+    Quit,
+}
+
+#[derive(Clone, Copy)]
+pub enum RunMode {
+    Continue,
+    // Runs until the stack is shallower than this depth
+    UntilReturn(usize),
+}
+
+impl RunMode {
+    pub fn should_stop(&self, machine: &Machine) -> bool {
+        match *self {
+            RunMode::Continue => false,
+            RunMode::UntilReturn(depth) => machine.registers().stack().len() < depth,
+        }
+    }
+}
+
+// Stdin is read on its own thread so the window keeps being redrawn while the
+// debugger waits for a command
+pub fn read_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+pub fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+pub fn execute(machine: &mut Machine, line: &str) -> Result<Action, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.first() {
+        Some(command) => *command,
+        None => return Ok(Action::Prompt),
+    };
+    let args = &words[1..];
+
+    match command {
+        "step" | "s" => {
+            let count = match args.first() {
+                Some(n) => try!(expect_number(n)),
+                None => 1,
+            };
+            for _ in 0..count {
+                if let Some(hit) = machine.next_breakpoint_hit() {
+                    print_hit(machine, &hit);
+                    break;
+                }
+                let pc = machine.registers().read_pc();
+                match try!(machine.step_in_frame().map_err(|e| e.to_string())) {
+                    StepOutcome::Executed(instruction) => println!("   {:#05x}  {}", pc, instruction),
+                    StepOutcome::WaitingForKey => println!("   {:#05x}  waiting for a key", pc),
+                    StepOutcome::Exited => {
+                        println!("Program has exited");
+                        break;
+                    }
+                }
+            }
+            print_location(machine);
+        }
+        "continue" | "c" => return Ok(Action::Run(RunMode::Continue)),
+        "until" if args == ["ret"] => {
+            let depth = machine.registers().stack().len();
+            if depth == 0 {
+                return Err("Not inside a subroutine".to_string());
+            }
+            return Ok(Action::Run(RunMode::UntilReturn(depth)));
+        }
+        "break" | "b" => {
+            let (args, condition) = try!(split_condition(args));
+            let trigger = match args.first() {
+                None if condition.is_none() => {
+                    print_breakpoints(machine);
+                    return Ok(Action::Prompt);
+                }
+                None => return Err("break expects an address before 'if'".to_string()),
+                Some(&"on") if args.len() == 2 => {
+                    Trigger::Opcode(try!(OpcodePattern::parse(args[1]).ok_or_else(|| {
+                        format!("Expected an opcode pattern like Dxyn, got '{}'", args[1])
+                    })))
+                }
+                Some(&"when") if args.len() > 1 => {
+                    Trigger::Condition(try!(parse_condition(&args[1..].join(" "))))
+                }
+                Some(addr) if args.len() == 1 => Trigger::Address(try!(expect_address(addr))),
+                Some(_) => return Err("Unexpected arguments to break, try 'help'".to_string()),
+            };
+            add_breakpoint(machine, trigger, condition);
+        }
+        "watch" | "w" => {
+            let (args, condition) = try!(split_condition(args));
+            let start = try!(expect_address(try!(args.first().ok_or("watch expects an address"))));
+            let mut len = 1;
+            let mut access = Access {
+                read: false,
+                write: true,
+                execute: false,
+            };
+            for arg in &args[1..] {
+                match Access::from_name(arg) {
+                    Some(kinds) => access = kinds,
+                    None => len = try!(expect_number(arg)),
+                }
+            }
+            if len == 0 || start as usize + len > 0x10000 {
+                return Err(format!("Can't watch {} bytes from {:#05x}", len, start));
+            }
+
+            let trigger = Trigger::Watch {
+                start: start,
+                end: (start as usize + len - 1) as u16,
+                access: access,
+            };
+            add_breakpoint(machine, trigger, condition);
+        }
+        "ignore" => {
+            if args.len() != 2 {
+                return Err("ignore expects a breakpoint number and a count".to_string());
+            }
+            let id = try!(expect_number(args[0]));
+            let count = try!(expect_number(args[1]));
+            match machine.breakpoints_mut().get_mut(id) {
+                Some(bp) => bp.ignore = count,
+                None => return Err(format!("No breakpoint #{}", id)),
+            }
+        }
+        "delete" | "d" => {
+            match args.first() {
+                Some(id) => {
+                    let id = try!(expect_number(id));
+                    if !machine.breakpoints_mut().remove(id) {
+                        return Err(format!("No breakpoint #{}", id));
+                    }
+                }
+                None => machine.breakpoints_mut().clear(),
+            }
+        }
+        "regs" | "r" => print_registers(machine),
+        "mem" | "m" => {
+            let start = try!(expect_address(try!(args.first().ok_or("mem expects an address"))));
+            let len = match args.get(1) {
+                Some(len) => try!(expect_number(len)),
+                None => 64,
+            };
+            print_memory(machine, start, len);
+        }
+        "set" => {
+            if args.len() != 2 {
+                return Err("set expects a register and a value".to_string());
+            }
+            try!(set_register(machine, args[0], try!(expect_number(args[1]))));
+        }
+        "disasm" => {
+            let start = match args.first() {
+                Some(addr) => try!(expect_address(addr)),
+                None => machine.registers().read_pc(),
+            };
+            let count = match args.get(1) {
+                Some(n) => try!(expect_number(n)),
+                None => 10,
+            };
+            print_disassembly(machine, start, count);
+        }
+        "stack" => {
+            let stack = machine.registers().stack();
+            if stack.is_empty() {
+                println!("Stack is empty");
+            }
+            for (depth, addr) in stack.iter().enumerate().rev() {
+                println!("#{}  returns to {:#05x}", depth, addr);
+            }
+        }
+        "help" | "h" | "?" => println!("{}", HELP),
+        "quit" | "q" => return Ok(Action::Quit),
+        _ => return Err(format!("Unknown command '{}', try 'help'", line.trim())),
+    }
+
+    Ok(Action::Prompt)
+}
+
+pub fn print_location(machine: &Machine) {
+    let pc = machine.registers().read_pc();
+    match instruction_at(machine, pc) {
+        Some(instruction) => println!("=> {:#05x}  {}", pc, instruction),
+        None => println!("=> {:#05x}  (not an instruction)", pc),
+    }
+}
+
+pub fn print_hit(machine: &Machine, hit: &Hit) {
+    let description = match machine.breakpoints().get(hit.id) {
+        Some(bp) => bp.trigger.to_string(),
+        None => String::new(),
+    };
+
+    match hit.access {
+        Some((kind, addr)) => {
+            println!("#{} {}: {} of {:#05x} at {:#05x}",
+                     hit.id,
+                     description,
+                     kind,
+                     addr,
+                     hit.pc)
+        }
+        None => println!("#{} {}: stopped at {:#05x}", hit.id, description, hit.pc),
+    }
+}
+
+fn add_breakpoint(machine: &mut Machine, trigger: Trigger, condition: Option<Condition>) {
+    let id = machine.breakpoints_mut().add(trigger, condition);
+    if let Some(bp) = machine.breakpoints().get(id) {
+        println!("#{}  {}", id, bp);
+    }
+}
+
+fn print_breakpoints(machine: &Machine) {
+    if machine.breakpoints().is_empty() {
+        println!("No breakpoints");
+    }
+    for (id, bp) in machine.breakpoints().iter() {
+        println!("#{}  {}", id, bp);
+    }
+}
+
+fn instruction_at(machine: &Machine, addr: u16) -> Option<Instruction> {
+    let high = machine.read_memory(addr);
+    let low = machine.read_memory(addr.wrapping_add(1));
+    let next_high = machine.read_memory(addr.wrapping_add(2)).unwrap_or(0);
+    let next_low = machine.read_memory(addr.wrapping_add(3)).unwrap_or(0);
+    let next = ((next_high as u16) << 8) | next_low as u16;
+    match (high, low) {
+        (Some(high), Some(low)) => decode_with_next(((high as u16) << 8) | low as u16, next).ok(),
+        _ => None,
+    }
+}
+
+fn print_registers(machine: &Machine) {
+    let reg = machine.registers();
+    for row in 0..2 {
+        let values: Vec<String> = (row * 8..row * 8 + 8)
+                                      .map(|x| format!("V{:X}={:02x}", x, reg.read_register(x)))
+                                      .collect();
+        println!("{}", values.join("  "));
+    }
+    println!("I={:#05x}  PC={:#05x}  SP={}  DT={:02x}  ST={:02x}",
+             reg.read_register_i(),
+             reg.read_pc(),
+             reg.stack().len(),
+             reg.read_delay_timer(),
+             reg.read_sound_timer());
+}
+
+fn print_memory(machine: &Machine, start: u16, len: usize) {
+    let mut addr = start as usize;
+    let end = start as usize + len;
+
+    while addr < end {
+        let row_end = (addr + 16).min(end);
+        let bytes: Vec<String> = (addr..row_end)
+                                     .map(|a| match machine.read_memory(a as u16) {
+                                         Some(byte) => format!("{:02x}", byte),
+                                         None => "--".to_string(),
+                                     })
+                                     .collect();
+        println!("{:#05x}  {}", addr, bytes.join(" "));
+        addr = row_end;
+    }
+}
+
+fn print_disassembly(machine: &Machine, start: u16, count: usize) {
+    let pc = machine.registers().read_pc();
+    let mut addr = start;
+
+    for _ in 0..count {
+        let marker = if addr == pc {
+            "=>"
+        } else if machine.breakpoints().has_address(addr) {
+            " *"
+        } else {
+            "  "
+        };
+
+        match instruction_at(machine, addr) {
+            Some(instruction) => println!("{} {:#05x}  {}", marker, addr, instruction),
+            None => {
+                let byte = machine.read_memory(addr).unwrap_or(0);
+                println!("{} {:#05x}  db {:#04x}", marker, addr, byte);
+            }
+        }
+
+        // F000 is followed by its 16-bit address on XO-CHIP
+        addr = match instruction_at(machine, addr) {
+            Some(Instruction::LdILong(_)) if machine.xo_chip() => addr.wrapping_add(4),
+            _ => addr.wrapping_add(2),
+        };
+    }
+}
+
+fn set_register(machine: &mut Machine, name: &str, value: usize) -> Result<(), String> {
+    let upper = name.to_uppercase();
+    let reg = machine.registers_mut();
+
+    match upper.as_str() {
+        "I" => reg.write_register_i(try!(fit(value, 0xffff)) as u16),
+        "PC" => reg.write_pc(try!(fit(value, 0xffff)) as u16),
+        "DT" => reg.write_delay_timer(try!(fit(value, 0xff)) as u8),
+        "ST" => reg.write_sound_timer(try!(fit(value, 0xff)) as u8),
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            let x = try!(u8::from_str_radix(&upper[1..], 16)
+                             .map_err(|_| format!("Unknown register '{}'", name)));
+            reg.write_register(x, try!(fit(value, 0xff)) as u8);
+        }
+        _ => return Err(format!("Unknown register '{}'", name)),
+    }
+
+    Ok(())
+}
+
+// Splits off a trailing `if <cond>`
+fn split_condition<'a, 'b>(args: &'a [&'b str])
+                           -> Result<(&'a [&'b str], Option<Condition>), String> {
+    match args.iter().position(|arg| *arg == "if") {
+        Some(index) => {
+            let condition = try!(parse_condition(&args[index + 1..].join(" ")));
+            Ok((&args[..index], Some(condition)))
+        }
+        None => Ok((args, None)),
+    }
+}
+
+// Parses `<operand> <comparison> <value>`, with or without spaces
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let found = ["==", "!=", "<=", ">=", "<", ">"]
+                    .iter()
+                    .filter_map(|op| text.find(op).map(|index| (index, *op)))
+                    .min_by_key(|&(index, op)| (index, 2 - op.len()));
+    let (index, op) = try!(found.ok_or_else(|| {
+        format!("Expected a condition like 'V3 == 0x10', got '{}'", text)
+    }));
+
+    let operand = try!(parse_operand(text[..index].trim()));
+    let value = try!(expect_number(text[index + op.len()..].trim()));
+    Ok(Condition {
+        operand: operand,
+        comparison: Comparison::from_name(op).unwrap(),
+        value: try!(fit(value, 0xffff)) as u16,
+    })
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    if text.starts_with('[') && text.ends_with(']') {
+        return Ok(Operand::Memory(try!(expect_address(&text[1..text.len() - 1]))));
+    }
+
+    let upper = text.to_uppercase();
+    match upper.as_str() {
+        "I" => Ok(Operand::I),
+        "PC" => Ok(Operand::Pc),
+        "SP" => Ok(Operand::Sp),
+        "DT" => Ok(Operand::Dt),
+        "ST" => Ok(Operand::St),
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            u8::from_str_radix(&upper[1..], 16)
+                .map(Operand::V)
+                .map_err(|_| format!("Unknown register '{}'", text))
+        }
+        _ => Err(format!("Unknown register '{}'", text)),
+    }
+}
+
+fn fit(value: usize, max: usize) -> Result<usize, String> {
+    if value > max {
+        return Err(format!("{:#x} does not fit, maximum {:#x}", value, max));
+    }
+    Ok(value)
+}
+
+fn expect_number(text: &str) -> Result<usize, String> {
+    parse_number(text).ok_or(format!("Expected a number, got '{}'", text))
+}
+
+fn expect_address(text: &str) -> Result<u16, String> {
+    let addr = try!(expect_number(text));
+    Ok(try!(fit(addr, 0xffff)) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::rom::Rom;
+
+    #[test]
+    fn step_stops_at_watchpoints() {
+        // ld V0 5; ld I 0x300; ld [I] V0; jp 0x206
+        let program = [0x60, 0x05, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x06];
+        let mut machine = Machine::new();
+        machine.load_rom(&Rom::from_bytes(&program)).unwrap();
+
+        execute(&mut machine, "watch 0x300").unwrap();
+        execute(&mut machine, "step 5").unwrap();
+        assert_eq!(machine.registers().read_pc(), 0x204);
+        assert_eq!(machine.read_memory(0x300), Some(0));
+        assert_eq!(machine.breakpoints().get(1).unwrap().hits, 1);
+
+        // The next step runs the instruction that set off the watchpoint
+        execute(&mut machine, "step").unwrap();
+        assert_eq!(machine.registers().read_pc(), 0x206);
+        assert_eq!(machine.read_memory(0x300), Some(5));
+    }
+}
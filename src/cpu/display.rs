@@ -0,0 +1,159 @@
+use super::error::EmuError;
+use super::savestate::{StateReader, StateWriter};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Each pixel holds one bit per bitplane, so a pixel's value doubles as its colour
+// index: 0 is off, 1 and 2 are the individual XO-CHIP planes and 3 is both.
+#[derive(Clone)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    planes: u8,
+    pixels: Vec<u8>,
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
+            planes: 1,
+            pixels: vec![0; LORES_WIDTH * LORES_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
+    }
+
+    // Switching resolution clears the screen, as it does on SUPER-CHIP 1.1
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = HIRES_WIDTH;
+            self.height = HIRES_HEIGHT;
+        } else {
+            self.width = LORES_WIDTH;
+            self.height = LORES_HEIGHT;
+        }
+        self.pixels = vec![0; self.width * self.height];
+    }
+
+    pub fn clear(&mut self) {
+        let keep = !self.planes;
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= keep;
+        }
+    }
+
+    // XORs a lit sprite pixel onto one plane, returning true if it turned a pixel off
+    pub fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let index = y * self.width + x;
+        let collision = self.pixels[index] & plane != 0;
+        self.pixels[index] ^= plane;
+        collision
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let source = if y >= rows {
+                    Some((x, y - rows))
+                } else {
+                    None
+                };
+                self.shift_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source = if y + rows < self.height {
+                    Some((x, y + rows))
+                } else {
+                    None
+                };
+                self.shift_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let source = if x >= columns {
+                    Some((x - columns, y))
+                } else {
+                    None
+                };
+                self.shift_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source = if x + columns < self.width {
+                    Some((x + columns, y))
+                } else {
+                    None
+                };
+                self.shift_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.width == HIRES_WIDTH);
+        writer.write_u8(self.planes);
+        writer.write_block(&self.pixels);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Display, EmuError> {
+        let mut display = Display::new();
+        display.set_hires(try!(reader.read_bool()));
+        display.select_planes(try!(reader.read_u8()));
+
+        let pixels = try!(reader.read_block());
+        if pixels.len() != display.pixels.len() {
+            return Err(EmuError::InvalidSaveState(format!("display holds {} pixels, expected {}",
+                                                          pixels.len(),
+                                                          display.pixels.len())));
+        }
+        display.pixels = pixels.to_vec();
+
+        Ok(display)
+    }
+
+    // Moves the selected planes of one pixel during a scroll, leaving the others alone
+    fn shift_pixel(&mut self, x: usize, y: usize, source: Option<(usize, usize)>) {
+        let incoming = match source {
+            Some((source_x, source_y)) => self.pixel(source_x, source_y) & self.planes,
+            None => 0,
+        };
+        let index = y * self.width + x;
+        self.pixels[index] = (self.pixels[index] & !self.planes) | incoming;
+    }
+}
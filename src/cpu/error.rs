@@ -0,0 +1,82 @@
+use super::instruction::Instruction;
+use super::breakpoint::Hit;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed(Instruction),
+    WaitingForKey,
+    Exited,
+}
+
+// How a call to `Machine::run_frame` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOutcome {
+    Completed,
+    Breakpoint(Hit),
+    // The caller's stop condition held; the frame resumes on the next call
+    Stopped,
+}
+
+#[derive(Debug)]
+pub enum EmuError {
+    InvalidOpcode { pc: u16, word: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: u16 },
+    RomTooLarge { size: usize, max: usize },
+    InvalidRom(String),
+    InvalidSaveState(String),
+    InvalidMovie(String),
+    InvalidFont(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmuError::InvalidOpcode { pc, word } => {
+                write!(f, "Invalid opcode {:#06x} at {:#x}", word, pc)
+            }
+            EmuError::StackOverflow { pc } => write!(f, "Stack overflow at {:#x}", pc),
+            EmuError::StackUnderflow { pc } => write!(f, "Stack underflow at {:#x}", pc),
+            EmuError::MemoryOutOfBounds { addr } => {
+                write!(f, "Memory access out of bounds at {:#x}", addr)
+            }
+            EmuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but at most {} bytes fit in memory", size, max)
+            }
+            EmuError::InvalidRom(ref reason) => write!(f, "Invalid ROM: {}", reason),
+            EmuError::InvalidSaveState(ref reason) => write!(f, "Invalid save state: {}", reason),
+            EmuError::InvalidMovie(ref reason) => write!(f, "Invalid movie: {}", reason),
+            EmuError::InvalidFont(ref reason) => write!(f, "Invalid font: {}", reason),
+            EmuError::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl Error for EmuError {
+    fn description(&self) -> &str {
+        match *self {
+            EmuError::InvalidOpcode { .. } => "invalid opcode",
+            EmuError::StackOverflow { .. } => "stack overflow",
+            EmuError::StackUnderflow { .. } => "stack underflow",
+            EmuError::MemoryOutOfBounds { .. } => "memory access out of bounds",
+            EmuError::RomTooLarge { .. } => "ROM too large",
+            EmuError::InvalidRom(_) => "invalid ROM",
+            EmuError::InvalidSaveState(_) => "invalid save state",
+            EmuError::InvalidMovie(_) => "invalid movie",
+            EmuError::InvalidFont(_) => "invalid font",
+            EmuError::Io(ref e) => e.description(),
+        }
+    }
+}
+
+impl From<io::Error> for EmuError {
+    fn from(e: io::Error) -> EmuError {
+        EmuError::Io(e)
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::error::EmuError;
+use super::rom;
+
+pub const SMALL_GLYPH_SIZE: usize = 5;
+pub const BIG_GLYPH_SIZE: usize = 10;
+
+// The small font starts the interpreter area and the big font follows it
+const FONT_ADDR: u16 = 0x000;
+
+// The font most interpreters since CHIP-48 use
+const STANDARD: [u8; 80] = [0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+                            0x20, 0x60, 0x20, 0x20, 0x70, // 1
+                            0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+                            0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+                            0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+                            0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+                            0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+                            0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+                            0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+                            0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+                            0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+                            0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+                            0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+                            0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+                            0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+                            0xf0, 0x80, 0xf0, 0x80, 0x80]; // F
+
+const COSMAC_VIP: [u8; 80] = [0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+                              0x60, 0x20, 0x20, 0x20, 0x70, // 1
+                              0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+                              0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+                              0xa0, 0xa0, 0xf0, 0x20, 0x20, // 4
+                              0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+                              0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+                              0xf0, 0x10, 0x10, 0x10, 0x10, // 7
+                              0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+                              0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+                              0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+                              0xf0, 0x50, 0x70, 0x50, 0xf0, // B
+                              0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+                              0xf0, 0x50, 0x50, 0x50, 0xf0, // D
+                              0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+                              0xf0, 0x80, 0xf0, 0x80, 0x80]; // F
+
+const DREAM_6800: [u8; 80] = [0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+                              0x40, 0x40, 0x40, 0x40, 0x40, // 1
+                              0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+                              0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+                              0x80, 0xa0, 0xa0, 0xe0, 0x20, // 4
+                              0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+                              0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+                              0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+                              0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+                              0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+                              0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+                              0xc0, 0xa0, 0xe0, 0xa0, 0xc0, // B
+                              0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+                              0xc0, 0xa0, 0xa0, 0xa0, 0xc0, // D
+                              0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+                              0xe0, 0x80, 0xc0, 0x80, 0x80]; // F
+
+const ETI_660: [u8; 80] = [0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+                           0x20, 0x20, 0x20, 0x20, 0x20, // 1
+                           0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+                           0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+                           0xa0, 0xa0, 0xe0, 0x20, 0x20, // 4
+                           0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+                           0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+                           0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+                           0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+                           0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+                           0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+                           0x80, 0x80, 0xe0, 0xa0, 0xe0, // B
+                           0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+                           0x20, 0x20, 0xe0, 0xa0, 0xe0, // D
+                           0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+                           0xe0, 0x80, 0xc0, 0x80, 0x80]; // F
+
+// SUPER-CHIP 1.1 8x10 digits. It has no A-F; Fx30 with those points past the end.
+const SCHIP: [u8; 100] = [0x3c, 0x7e, 0xe7, 0xc3, 0xc3, 0xc3, 0xc3, 0xe7, 0x7e, 0x3c, // 0
+                          0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c, // 1
+                          0x3e, 0x7f, 0xc3, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xff, 0xff, // 2
+                          0x3c, 0x7e, 0xc3, 0x03, 0x0e, 0x0e, 0x03, 0xc3, 0x7e, 0x3c, // 3
+                          0x06, 0x0e, 0x1e, 0x36, 0x66, 0xc6, 0xff, 0xff, 0x06, 0x06, // 4
+                          0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfe, 0x03, 0xc3, 0x7e, 0x3c, // 5
+                          0x3e, 0x7c, 0xc0, 0xc0, 0xfc, 0xfe, 0xc3, 0xc3, 0x7e, 0x3c, // 6
+                          0xff, 0xff, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+                          0x3c, 0x7e, 0xc3, 0xc3, 0x7e, 0x7e, 0xc3, 0xc3, 0x7e, 0x3c, // 8
+                          0x3c, 0x7e, 0xc3, 0xc3, 0x7f, 0x3f, 0x03, 0x03, 0x3e, 0x7c]; // 9
+
+// The SUPER-CHIP digits with A-F added, as in Octo
+const OCTO: [u8; 160] = [0x3c, 0x7e, 0xe7, 0xc3, 0xc3, 0xc3, 0xc3, 0xe7, 0x7e, 0x3c, // 0
+                         0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c, // 1
+                         0x3e, 0x7f, 0xc3, 0x06, 0x0c, 0x18, 0x30, 0x60, 0xff, 0xff, // 2
+                         0x3c, 0x7e, 0xc3, 0x03, 0x0e, 0x0e, 0x03, 0xc3, 0x7e, 0x3c, // 3
+                         0x06, 0x0e, 0x1e, 0x36, 0x66, 0xc6, 0xff, 0xff, 0x06, 0x06, // 4
+                         0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfe, 0x03, 0xc3, 0x7e, 0x3c, // 5
+                         0x3e, 0x7c, 0xc0, 0xc0, 0xfc, 0xfe, 0xc3, 0xc3, 0x7e, 0x3c, // 6
+                         0xff, 0xff, 0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+                         0x3c, 0x7e, 0xc3, 0xc3, 0x7e, 0x7e, 0xc3, 0xc3, 0x7e, 0x3c, // 8
+                         0x3c, 0x7e, 0xc3, 0xc3, 0x7f, 0x3f, 0x03, 0x03, 0x3e, 0x7c, // 9
+                         0x3c, 0x7e, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, // A
+                         0xfc, 0xfe, 0xc3, 0xc3, 0xfe, 0xfe, 0xc3, 0xc3, 0xfe, 0xfc, // B
+                         0x3c, 0x7e, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0x7e, 0x3c, // C
+                         0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc, // D
+                         0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xff, 0xff, // E
+                         0xff, 0xff, 0xc0, 0xc0, 0xfc, 0xfc, 0xc0, 0xc0, 0xc0, 0xc0]; // F
+
+pub const SMALL_FONT_NAMES: [&'static str; 4] = ["standard", "cosmac_vip", "dream6800", "eti660"];
+pub const BIG_FONT_NAMES: [&'static str; 2] = ["octo", "schip"];
+
+// Glyph data for Fx29 and Fx30. Each set holds 5-byte or 10-byte glyphs for the hex
+// digits in order, though a big font may stop short of F.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FontSet {
+    pub small: Vec<u8>,
+    pub big: Vec<u8>,
+}
+
+impl Default for FontSet {
+    fn default() -> FontSet {
+        FontSet {
+            small: STANDARD.to_vec(),
+            big: OCTO.to_vec(),
+        }
+    }
+}
+
+impl FontSet {
+    pub fn set_small(&mut self, name: &str) -> Result<(), String> {
+        self.small = match name {
+            "standard" => STANDARD.to_vec(),
+            "cosmac_vip" => COSMAC_VIP.to_vec(),
+            "dream6800" => DREAM_6800.to_vec(),
+            "eti660" => ETI_660.to_vec(),
+            _ => {
+                return Err(format!("Unknown font '{}', expected one of: {}",
+                                   name,
+                                   SMALL_FONT_NAMES.join(", ")))
+            }
+        };
+        Ok(())
+    }
+
+    pub fn set_big(&mut self, name: &str) -> Result<(), String> {
+        self.big = match name {
+            "octo" => OCTO.to_vec(),
+            "schip" => SCHIP.to_vec(),
+            _ => {
+                return Err(format!("Unknown big font '{}', expected one of: {}",
+                                   name,
+                                   BIG_FONT_NAMES.join(", ")))
+            }
+        };
+        Ok(())
+    }
+
+    // A font file holds 16 small glyphs, optionally followed by 10 or 16 big ones
+    pub fn load_file(&mut self, path: &Path) -> Result<(), EmuError> {
+        let mut file = try!(File::open(path));
+        let mut data = Vec::new();
+        try!(file.read_to_end(&mut data));
+
+        let small_len = 16 * SMALL_GLYPH_SIZE;
+        let big_len = data.len().saturating_sub(small_len);
+        if data.len() < small_len ||
+           (big_len != 0 && big_len != 10 * BIG_GLYPH_SIZE && big_len != 16 * BIG_GLYPH_SIZE) {
+            return Err(EmuError::InvalidFont(format!("{} bytes; expected {}, {} or {}",
+                                                     data.len(),
+                                                     small_len,
+                                                     small_len + 10 * BIG_GLYPH_SIZE,
+                                                     small_len + 16 * BIG_GLYPH_SIZE)));
+        }
+
+        self.small = data[..small_len].to_vec();
+        if big_len > 0 {
+            self.big = data[small_len..].to_vec();
+        }
+        Ok(())
+    }
+
+    // Checksum of both fonts, which movies record since Fx29 and Fx30 read them
+    pub fn crc32(&self) -> u32 {
+        let mut data = self.small.clone();
+        data.extend_from_slice(&self.big);
+        rom::crc32(&data)
+    }
+
+    pub fn layout(&self) -> FontLayout {
+        FontLayout {
+            small_addr: FONT_ADDR,
+            big_addr: FONT_ADDR + self.small.len() as u16,
+        }
+    }
+}
+
+// Where each font ended up in memory, which is what Fx29 and Fx30 point I at
+#[derive(Clone, Copy, Debug)]
+pub struct FontLayout {
+    pub small_addr: u16,
+    pub big_addr: u16,
+}
+
+impl Default for FontLayout {
+    fn default() -> FontLayout {
+        FontSet::default().layout()
+    }
+}
+
+impl FontLayout {
+    pub fn small_glyph(&self, digit: u8) -> u16 {
+        self.small_addr + (digit & 0x0f) as u16 * SMALL_GLYPH_SIZE as u16
+    }
+
+    pub fn big_glyph(&self, digit: u8) -> u16 {
+        self.big_addr + (digit & 0x0f) as u16 * BIG_GLYPH_SIZE as u16
+    }
+}
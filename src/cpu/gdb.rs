@@ -0,0 +1,554 @@
+use super::machine::Machine;
+use super::breakpoint::{Access, Hit, Trigger};
+use super::error::StepOutcome;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Registers in the order of the target description: V0-VF, I, PC, SP, DT and ST
+const REGISTER_COUNT: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub enum Request {
+    Packet(String),
+    // Ctrl-C from the debugger
+    Interrupt,
+}
+
+// What the run loop should do after a packet
+pub enum Resume {
+    Stay,
+    Continue,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    stream: TcpStream,
+    input: Vec<u8>,
+    ack: bool,
+    // Breakpoints inserted with Z packets, by type and address
+    inserted: HashMap<(u8, u16), usize>,
+}
+
+impl GdbStub {
+    // Blocks until a debugger connects
+    pub fn listen(port: u16) -> io::Result<GdbStub> {
+        let listener = try!(TcpListener::bind(("127.0.0.1", port)));
+        println!("Waiting for GDB on 127.0.0.1:{}", port);
+
+        let (stream, addr) = try!(listener.accept());
+        println!("GDB connected from {}", addr);
+        try!(stream.set_nodelay(true));
+        try!(stream.set_nonblocking(true));
+
+        Ok(GdbStub {
+            stream: stream,
+            input: Vec::new(),
+            ack: true,
+            inserted: HashMap::new(),
+        })
+    }
+
+    // Returns the next complete request without waiting for one. A closed connection
+    // is reported as an error once everything it sent has been handled.
+    pub fn poll(&mut self) -> io::Result<Option<Request>> {
+        if let Some(request) = try!(self.next_request()) {
+            return Ok(Some(request));
+        }
+
+        let mut buf = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"))
+                }
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.next_request()
+    }
+
+    pub fn handle(&mut self, machine: &mut Machine, packet: &str) -> io::Result<Resume> {
+        let reply = match packet.chars().next() {
+            Some('?') => stop_reply(SIGTRAP),
+            Some('g') => {
+                (0..REGISTER_COUNT)
+                    .map(|n| encode_register(n, read_register(machine, n)))
+                    .collect()
+            }
+            Some('G') => write_all_registers(machine, &packet[1..]),
+            Some('p') => {
+                match parse_hex(&packet[1..]) {
+                    Some(n) if (n as usize) < REGISTER_COUNT => {
+                        encode_register(n as usize, read_register(machine, n as usize))
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            Some('P') => {
+                let mut parts = packet[1..].splitn(2, '=');
+                let n = parts.next().and_then(parse_hex).map(|n| n as usize);
+                let value = match (n, parts.next()) {
+                    (Some(n), Some(hex)) => decode_register(n, hex.as_bytes()),
+                    _ => None,
+                };
+                match (n, value) {
+                    (Some(n), Some(value)) if write_register(machine, n, value) => {
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            Some('m') => read_memory(machine, &packet[1..]),
+            Some('M') => write_memory(machine, &packet[1..]),
+            Some('c') => {
+                if let Some(addr) = parse_hex(&packet[1..]) {
+                    machine.registers_mut().write_pc(addr as u16);
+                }
+                return Ok(Resume::Continue);
+            }
+            Some('s') => {
+                if let Some(addr) = parse_hex(&packet[1..]) {
+                    machine.registers_mut().write_pc(addr as u16);
+                }
+                match machine.step_in_frame() {
+                    Ok(StepOutcome::Exited) => exit_reply(),
+                    Ok(_) => stop_reply(SIGTRAP),
+                    Err(e) => {
+                        println!("Emulation stopped: {}", e);
+                        stop_reply(SIGILL)
+                    }
+                }
+            }
+            Some('Z') => self.insert_breakpoint(machine, &packet[1..]),
+            Some('z') => self.remove_breakpoint(machine, &packet[1..]),
+            Some('H') => "OK".to_string(),
+            Some('k') => return Ok(Resume::Kill),
+            Some('D') => {
+                try!(self.send("OK"));
+                return Ok(Resume::Detach);
+            }
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                read_target_xml(&packet["qXfer:features:read:target.xml:".len()..])
+            }
+            _ if packet == "QStartNoAckMode" => {
+                try!(self.send("OK"));
+                self.ack = false;
+                return Ok(Resume::Stay);
+            }
+            _ if packet == "qAttached" => "1".to_string(),
+            _ if packet == "qfThreadInfo" => "m1".to_string(),
+            _ if packet == "qsThreadInfo" => "l".to_string(),
+            _ if packet == "qC" => "QC1".to_string(),
+            // An empty reply tells the debugger a packet isn't supported
+            _ => String::new(),
+        };
+
+        try!(self.send(&reply));
+        Ok(Resume::Stay)
+    }
+
+    pub fn report_hit(&mut self, hit: &Hit) -> io::Result<()> {
+        let kind = self.inserted
+                       .iter()
+                       .find(|&(_, id)| *id == hit.id)
+                       .map(|(&(kind, _), _)| kind);
+        let reply = match (hit.access, kind) {
+            (Some((_, addr)), Some(2)) => format!("T{:02x}watch:{:x};", SIGTRAP, addr),
+            (Some((_, addr)), Some(3)) => format!("T{:02x}rwatch:{:x};", SIGTRAP, addr),
+            (Some((_, addr)), Some(4)) => format!("T{:02x}awatch:{:x};", SIGTRAP, addr),
+            _ => stop_reply(SIGTRAP),
+        };
+        self.send(&reply)
+    }
+
+    pub fn report_interrupt(&mut self) -> io::Result<()> {
+        self.send(&stop_reply(SIGINT))
+    }
+
+    pub fn report_error(&mut self) -> io::Result<()> {
+        self.send(&stop_reply(SIGILL))
+    }
+
+    pub fn report_exit(&mut self) -> io::Result<()> {
+        self.send(&exit_reply())
+    }
+
+    // Removes everything the debugger inserted, so the program runs freely after it leaves
+    pub fn remove_all_breakpoints(&mut self, machine: &mut Machine) {
+        for (_, id) in self.inserted.drain() {
+            machine.breakpoints_mut().remove(id);
+        }
+    }
+
+    // Z and z packets carry `type,addr,kind`, where kind is the length for watchpoints
+    fn insert_breakpoint(&mut self, machine: &mut Machine, args: &str) -> String {
+        let (kind, addr, len) = match parse_breakpoint(args) {
+            Some(parsed) => parsed,
+            None => return "E01".to_string(),
+        };
+        if self.inserted.contains_key(&(kind, addr)) {
+            return "OK".to_string();
+        }
+
+        let access = |read, write| {
+            Access {
+                read: read,
+                write: write,
+                execute: false,
+            }
+        };
+        let trigger = match kind {
+            0 | 1 => Trigger::Address(addr),
+            2 | 3 | 4 => {
+                let end = addr as usize + len.max(1) - 1;
+                if end > 0xffff {
+                    return "E01".to_string();
+                }
+                Trigger::Watch {
+                    start: addr,
+                    end: end as u16,
+                    access: match kind {
+                        2 => access(false, true),
+                        3 => access(true, false),
+                        _ => access(true, true),
+                    },
+                }
+            }
+            _ => return String::new(),
+        };
+
+        let id = machine.breakpoints_mut().add(trigger, None);
+        self.inserted.insert((kind, addr), id);
+        "OK".to_string()
+    }
+
+    fn remove_breakpoint(&mut self, machine: &mut Machine, args: &str) -> String {
+        let (kind, addr, _) = match parse_breakpoint(args) {
+            Some(parsed) => parsed,
+            None => return "E01".to_string(),
+        };
+        if let Some(id) = self.inserted.remove(&(kind, addr)) {
+            machine.breakpoints_mut().remove(id);
+        }
+        "OK".to_string()
+    }
+
+    fn next_request(&mut self) -> io::Result<Option<Request>> {
+        loop {
+            match self.input.first().cloned() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.input.remove(0);
+                    return Ok(Some(Request::Interrupt));
+                }
+                Some(b'$') => {
+                    let end = match self.input.iter().position(|&b| b == b'#') {
+                        Some(end) if self.input.len() >= end + 3 => end,
+                        _ => return Ok(None),
+                    };
+                    let data = self.input[1..end].to_vec();
+                    let checksum = hex_byte(&self.input[end + 1..end + 3]);
+                    self.input.drain(..end + 3);
+
+                    if checksum != Some(checksum_of(&data)) {
+                        if self.ack {
+                            try!(self.write_raw(b"-"));
+                        }
+                        continue;
+                    }
+                    if self.ack {
+                        try!(self.write_raw(b"+"));
+                    }
+                    return Ok(Some(Request::Packet(String::from_utf8_lossy(&data).into_owned())));
+                }
+                // Acknowledgements, and anything else between packets
+                Some(_) => {
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.write_raw(packet.as_bytes())
+    }
+
+    // Replies go out with the socket blocking, so a full send buffer can't cut them short
+    fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        try!(self.stream.set_nonblocking(false));
+        let result = self.stream.write_all(data);
+        try!(self.stream.set_nonblocking(true));
+        result
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn exit_reply() -> String {
+    "W00".to_string()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// Packets are decoded lossily, so hex data is read as bytes: slicing the text could
+// land inside a replacement character
+fn hex_byte(digits: &[u8]) -> Option<u8> {
+    match digits {
+        &[high, low] => {
+            match ((high as char).to_digit(16), (low as char).to_digit(16)) {
+                (Some(high), Some(low)) => Some((high << 4 | low) as u8),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn parse_breakpoint(args: &str) -> Option<(u8, u16, usize)> {
+    let parts: Vec<Option<u32>> = args.split(',').map(parse_hex).collect();
+    match parts.as_slice() {
+        &[Some(kind), Some(addr), Some(len)] if kind <= 4 && addr <= 0xffff => {
+            Some((kind as u8, addr as u16, len as usize))
+        }
+        _ => None,
+    }
+}
+
+fn register_bits(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 16,
+        _ => 8,
+    }
+}
+
+fn read_register(machine: &Machine, n: usize) -> u16 {
+    let reg = machine.registers();
+    match n {
+        REG_I => reg.read_register_i(),
+        REG_PC => reg.read_pc(),
+        REG_SP => reg.stack().len() as u16,
+        REG_DT => reg.read_delay_timer() as u16,
+        REG_ST => reg.read_sound_timer() as u16,
+        _ => reg.read_register(n as u8) as u16,
+    }
+}
+
+// The stack depth can't be changed this way, so writing SP only succeeds if it
+// leaves the value as it was
+fn write_register(machine: &mut Machine, n: usize, value: u32) -> bool {
+    if n >= REGISTER_COUNT || value >= 1 << register_bits(n) {
+        return false;
+    }
+
+    let reg = machine.registers_mut();
+    match n {
+        REG_I => reg.write_register_i(value as u16),
+        REG_PC => reg.write_pc(value as u16),
+        REG_SP => return value as usize == reg.stack().len(),
+        REG_DT => reg.write_delay_timer(value as u8),
+        REG_ST => reg.write_sound_timer(value as u8),
+        _ => reg.write_register(n as u8, value as u8),
+    }
+    true
+}
+
+// A target description can't give a byte order, and GDB reads one that names no
+// architecture as little-endian, so I and PC go out low byte first
+fn encode_register(n: usize, value: u16) -> String {
+    match register_bits(n) {
+        16 => format!("{:02x}{:02x}", value & 0xff, value >> 8),
+        _ => format!("{:02x}", value),
+    }
+}
+
+fn decode_register(n: usize, hex: &[u8]) -> Option<u32> {
+    if hex.len() != register_bits(n) / 4 {
+        return None;
+    }
+    hex.chunks(2)
+       .rev()
+       .fold(Some(0), |value, digits| {
+           value.and_then(|value| hex_byte(digits).map(|byte| value << 8 | byte as u32))
+       })
+}
+
+fn write_all_registers(machine: &mut Machine, hex: &str) -> String {
+    let hex = hex.as_bytes();
+    let mut offset = 0;
+    let mut values = Vec::new();
+    for n in 0..REGISTER_COUNT {
+        let digits = register_bits(n) / 4;
+        match hex.get(offset..offset + digits).and_then(|hex| decode_register(n, hex)) {
+            Some(value) => values.push(value),
+            None => return "E01".to_string(),
+        }
+        offset += digits;
+    }
+
+    for (n, value) in values.into_iter().enumerate() {
+        if n != REG_SP {
+            write_register(machine, n, value);
+        }
+    }
+    "OK".to_string()
+}
+
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let parts: Vec<Option<u32>> = text.splitn(2, ',').map(parse_hex).collect();
+    match parts.as_slice() {
+        &[Some(addr), Some(len)] if addr <= 0xffff => Some((addr as u16, len as usize)),
+        _ => None,
+    }
+}
+
+// Reads stop at the end of memory, as the protocol allows
+fn read_memory(machine: &Machine, args: &str) -> String {
+    let (addr, len) = match parse_range(args) {
+        Some(range) => range,
+        None => return "E01".to_string(),
+    };
+
+    let bytes: String = (0..len)
+                            .map(|offset| machine.read_memory(addr.wrapping_add(offset as u16)))
+                            .take_while(|byte| byte.is_some())
+                            .map(|byte| format!("{:02x}", byte.unwrap()))
+                            .collect();
+    if bytes.is_empty() && len > 0 {
+        return "E01".to_string();
+    }
+    bytes
+}
+
+fn write_memory(machine: &mut Machine, args: &str) -> String {
+    let mut parts = args.splitn(2, ':');
+    let (addr, len) = match parts.next().and_then(parse_range) {
+        Some(range) => range,
+        None => return "E01".to_string(),
+    };
+    let data = parts.next().unwrap_or("").as_bytes();
+    if data.len() != len * 2 {
+        return "E01".to_string();
+    }
+
+    for (offset, digits) in data.chunks(2).enumerate() {
+        let byte = match hex_byte(digits) {
+            Some(byte) => byte,
+            None => return "E01".to_string(),
+        };
+        if machine.write_memory(addr.wrapping_add(offset as u16), byte).is_err() {
+            return "E01".to_string();
+        }
+    }
+    "OK".to_string()
+}
+
+fn target_xml() -> String {
+    let mut registers = String::new();
+    for n in 0..16 {
+        registers.push_str(&format!("    <reg name=\"v{:x}\" bitsize=\"8\" regnum=\"{}\"/>\n", n, n));
+    }
+
+    format!("<?xml version=\"1.0\"?>
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">
+<target version=\"1.0\">
+  <feature name=\"org.rust8.chip8\">
+{}    <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>
+    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>
+    <reg name=\"sp\" bitsize=\"8\"/>
+    <reg name=\"dt\" bitsize=\"8\"/>
+    <reg name=\"st\" bitsize=\"8\"/>
+  </feature>
+</target>
+",
+            registers)
+}
+
+// Replies to `offset,length` with a chunk of the description, marked `l` when it's the last
+fn read_target_xml(args: &str) -> String {
+    let xml = target_xml();
+    let (offset, len) = match parse_range(args) {
+        Some((offset, len)) => (offset as usize, len),
+        None => return "E01".to_string(),
+    };
+    if offset >= xml.len() {
+        return "l".to_string();
+    }
+
+    let end = (offset + len).min(xml.len());
+    let marker = if end == xml.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &xml[offset..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_are_little_endian() {
+        let mut machine = Machine::new();
+        machine.registers_mut().write_register_i(0x1234);
+
+        assert_eq!(encode_register(REG_I, read_register(&machine, REG_I)), "3412");
+        assert_eq!(decode_register(REG_PC, b"0602"), Some(0x206));
+        assert_eq!(decode_register(REG_PC, b"206"), None);
+        assert_eq!(decode_register(0, b"ff"), Some(0xff));
+    }
+
+    #[test]
+    fn write_all_registers_reads_the_g_layout() {
+        let mut machine = Machine::new();
+        let hex = format!("{}{}{}{}{}{}",
+                          "0102030405060708090a0b0c0d0e0f10",
+                          "3412",
+                          "0602",
+                          "00",
+                          "3c",
+                          "01");
+
+        assert_eq!(write_all_registers(&mut machine, &hex), "OK");
+        let reg = machine.registers();
+        assert_eq!(reg.read_register(0xf), 0x10);
+        assert_eq!(reg.read_register_i(), 0x1234);
+        assert_eq!(reg.read_pc(), 0x206);
+        assert_eq!(reg.read_delay_timer(), 0x3c);
+        assert_eq!(reg.read_sound_timer(), 0x01);
+    }
+
+    #[test]
+    fn write_memory_rejects_data_that_is_not_hex() {
+        let mut machine = Machine::new();
+
+        // Four bytes, but the two-byte character straddles the first pair of digits
+        assert_eq!(write_memory(&mut machine, "300,2:a\u{e9}c"), "E01");
+        assert_eq!(write_memory(&mut machine, "300,2:abzz"), "E01");
+        assert_eq!(write_memory(&mut machine, "300,2:abcd"), "OK");
+        assert_eq!(machine.read_memory(0x300), Some(0xab));
+        assert_eq!(machine.read_memory(0x301), Some(0xcd));
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(u16),
+    Cls,
+    Ret,
+    Scd { n: u8 },
+    Scu { n: u8 },
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    Jp(u16),
+    Call(u16),
+    SeVxByte { x: u8, kk: u8 },
+    SneVxByte { x: u8, kk: u8 },
+    SeVxVy { x: u8, y: u8 },
+    SaveVxVy { x: u8, y: u8 },
+    LoadVxVy { x: u8, y: u8 },
+    LdVxByte { x: u8, kk: u8 },
+    AddVxByte { x: u8, kk: u8 },
+    LdVxVy { x: u8, y: u8 },
+    OrVxVy { x: u8, y: u8 },
+    AndVxVy { x: u8, y: u8 },
+    XorVxVy { x: u8, y: u8 },
+    AddVxVy { x: u8, y: u8 },
+    SubVxVy { x: u8, y: u8 },
+    ShrVxVy { x: u8, y: u8 },
+    SubnVxVy { x: u8, y: u8 },
+    ShlVxVy { x: u8, y: u8 },
+    SneVxVy { x: u8, y: u8 },
+    LdIAddr(u16),
+    // XO-CHIP's F000 nnnn, whose address is the word after the opcode
+    LdILong(u16),
+    JpV0(u16),
+    RndVxByte { x: u8, kk: u8 },
+    Drw { x: u8, y: u8, n: u8 },
+    SkpVx { x: u8 },
+    SknpVx { x: u8 },
+    Plane { n: u8 },
+    Audio,
+    PitchVx { x: u8 },
+    LdVxDt { x: u8 },
+    LdVxK { x: u8 },
+    LdDtVx { x: u8 },
+    LdStVx { x: u8 },
+    AddIVx { x: u8 },
+    LdFVx { x: u8 },
+    LdHfVx { x: u8 },
+    LdBVx { x: u8 },
+    LdIVx { x: u8 },
+    LdVxI { x: u8 },
+    LdRVx { x: u8 },
+    LdVxR { x: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub word: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unrecognized opcode: {:#06x}", self.word)
+    }
+}
+
+pub fn decode(word: u16) -> Result<Instruction, DecodeError> {
+    let x = ((word >> 8) & 0x0f) as u8;
+    let y = ((word >> 4) & 0x0f) as u8;
+    let n = (word & 0x000f) as u8;
+    let kk = (word & 0x00ff) as u8;
+    let addr = word & 0x0fff;
+
+    let instruction = match word >> 12 {
+        0x0 => {
+            match word {
+                0x00e0 => Instruction::Cls,
+                0x00ee => Instruction::Ret,
+                0x00fb => Instruction::Scr,
+                0x00fc => Instruction::Scl,
+                0x00fd => Instruction::Exit,
+                0x00fe => Instruction::Low,
+                0x00ff => Instruction::High,
+                _ if word & 0xfff0 == 0x00c0 => Instruction::Scd { n: n },
+                _ if word & 0xfff0 == 0x00d0 => Instruction::Scu { n: n },
+                _ => Instruction::Sys(addr),
+            }
+        }
+        0x1 => Instruction::Jp(addr),
+        0x2 => Instruction::Call(addr),
+        0x3 => Instruction::SeVxByte { x: x, kk: kk },
+        0x4 => Instruction::SneVxByte { x: x, kk: kk },
+        0x5 if n == 0 => Instruction::SeVxVy { x: x, y: y },
+        0x5 if n == 2 => Instruction::SaveVxVy { x: x, y: y },
+        0x5 if n == 3 => Instruction::LoadVxVy { x: x, y: y },
+        0x6 => Instruction::LdVxByte { x: x, kk: kk },
+        0x7 => Instruction::AddVxByte { x: x, kk: kk },
+        0x8 => {
+            match n {
+                0x0 => Instruction::LdVxVy { x: x, y: y },
+                0x1 => Instruction::OrVxVy { x: x, y: y },
+                0x2 => Instruction::AndVxVy { x: x, y: y },
+                0x3 => Instruction::XorVxVy { x: x, y: y },
+                0x4 => Instruction::AddVxVy { x: x, y: y },
+                0x5 => Instruction::SubVxVy { x: x, y: y },
+                0x6 => Instruction::ShrVxVy { x: x, y: y },
+                0x7 => Instruction::SubnVxVy { x: x, y: y },
+                0xe => Instruction::ShlVxVy { x: x, y: y },
+                _ => return Err(DecodeError { word: word }),
+            }
+        }
+        0x9 if n == 0 => Instruction::SneVxVy { x: x, y: y },
+        0xa => Instruction::LdIAddr(addr),
+        0xb => Instruction::JpV0(addr),
+        0xc => Instruction::RndVxByte { x: x, kk: kk },
+        0xd => Instruction::Drw { x: x, y: y, n: n },
+        0xe => {
+            match kk {
+                0x9e => Instruction::SkpVx { x: x },
+                0xa1 => Instruction::SknpVx { x: x },
+                _ => return Err(DecodeError { word: word }),
+            }
+        }
+        0xf => {
+            match kk {
+                0x00 if x == 0 => Instruction::LdILong(0),
+                0x01 => Instruction::Plane { n: x },
+                0x02 if x == 0 => Instruction::Audio,
+                0x3a => Instruction::PitchVx { x: x },
+                0x07 => Instruction::LdVxDt { x: x },
+                0x0a => Instruction::LdVxK { x: x },
+                0x15 => Instruction::LdDtVx { x: x },
+                0x18 => Instruction::LdStVx { x: x },
+                0x1e => Instruction::AddIVx { x: x },
+                0x29 => Instruction::LdFVx { x: x },
+                0x30 => Instruction::LdHfVx { x: x },
+                0x33 => Instruction::LdBVx { x: x },
+                0x55 => Instruction::LdIVx { x: x },
+                0x65 => Instruction::LdVxI { x: x },
+                0x75 => Instruction::LdRVx { x: x },
+                0x85 => Instruction::LdVxR { x: x },
+                _ => return Err(DecodeError { word: word }),
+            }
+        }
+        _ => return Err(DecodeError { word: word }),
+    };
+
+    Ok(instruction)
+}
+
+// `decode` only sees one word, so it leaves F000's address at 0. Whatever fetches the
+// instruction passes the word that follows it here to fill the address in.
+pub fn decode_with_next(word: u16, next: u16) -> Result<Instruction, DecodeError> {
+    match try!(decode(word)) {
+        Instruction::LdILong(_) => Ok(Instruction::LdILong(next)),
+        instruction => Ok(instruction),
+    }
+}
+
+impl Instruction {
+    // SUPER-CHIP additions, which XO-CHIP also has. Dxy0 is left out: it is a valid sprite
+    // with no rows everywhere else.
+    pub fn is_schip(&self) -> bool {
+        match *self {
+            Instruction::Scd { .. } |
+            Instruction::Scr |
+            Instruction::Scl |
+            Instruction::Exit |
+            Instruction::Low |
+            Instruction::High |
+            Instruction::LdHfVx { .. } |
+            Instruction::LdRVx { .. } |
+            Instruction::LdVxR { .. } => true,
+            _ => false,
+        }
+    }
+
+    // Instructions that only exist on XO-CHIP and are rejected on other platforms
+    pub fn is_xo_chip(&self) -> bool {
+        match *self {
+            Instruction::Scu { .. } |
+            Instruction::SaveVxVy { .. } |
+            Instruction::LoadVxVy { .. } |
+            Instruction::LdILong(_) |
+            Instruction::Plane { .. } |
+            Instruction::Audio |
+            Instruction::PitchVx { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(addr) => write!(f, "sys {:#x}", addr),
+            Instruction::Cls => write!(f, "cls"),
+            Instruction::Ret => write!(f, "ret"),
+            Instruction::Scd { n } => write!(f, "scd {}", n),
+            Instruction::Scu { n } => write!(f, "scu {}", n),
+            Instruction::Scr => write!(f, "scr"),
+            Instruction::Scl => write!(f, "scl"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::Low => write!(f, "low"),
+            Instruction::High => write!(f, "high"),
+            Instruction::Jp(addr) => write!(f, "jmp {:#x}", addr),
+            Instruction::Call(addr) => write!(f, "call {:#x}", addr),
+            Instruction::SeVxByte { x, kk } => write!(f, "se V{:X} {:#x}", x, kk),
+            Instruction::SneVxByte { x, kk } => write!(f, "sne V{:X} {:#x}", x, kk),
+            Instruction::SeVxVy { x, y } => write!(f, "se V{:X} V{:X}", x, y),
+            Instruction::SaveVxVy { x, y } => write!(f, "save V{:X} V{:X}", x, y),
+            Instruction::LoadVxVy { x, y } => write!(f, "load V{:X} V{:X}", x, y),
+            Instruction::LdVxByte { x, kk } => write!(f, "ld V{:X} {:#x}", x, kk),
+            Instruction::AddVxByte { x, kk } => write!(f, "add V{:X} {:#x}", x, kk),
+            Instruction::LdVxVy { x, y } => write!(f, "ld V{:X} V{:X}", x, y),
+            Instruction::OrVxVy { x, y } => write!(f, "or V{:X} V{:X}", x, y),
+            Instruction::AndVxVy { x, y } => write!(f, "and V{:X} V{:X}", x, y),
+            Instruction::XorVxVy { x, y } => write!(f, "xor V{:X} V{:X}", x, y),
+            Instruction::AddVxVy { x, y } => write!(f, "add V{:X} V{:X}", x, y),
+            Instruction::SubVxVy { x, y } => write!(f, "sub V{:X} V{:X}", x, y),
+            Instruction::ShrVxVy { x, y } => write!(f, "shr V{:X} V{:X}", x, y),
+            Instruction::SubnVxVy { x, y } => write!(f, "subn V{:X} V{:X}", x, y),
+            Instruction::ShlVxVy { x, y } => write!(f, "shl V{:X} V{:X}", x, y),
+            Instruction::SneVxVy { x, y } => write!(f, "sne V{:X} V{:X}", x, y),
+            Instruction::LdIAddr(addr) => write!(f, "ld I {:#x}", addr),
+            Instruction::LdILong(addr) => write!(f, "ld I long {:#x}", addr),
+            Instruction::JpV0(addr) => write!(f, "jp V0 {:#x}", addr),
+            Instruction::RndVxByte { x, kk } => write!(f, "rnd V{:X} {:#x}", x, kk),
+            Instruction::Drw { x, y, n } => write!(f, "drw V{:X} V{:X} {}", x, y, n),
+            Instruction::SkpVx { x } => write!(f, "skp V{:X}", x),
+            Instruction::SknpVx { x } => write!(f, "sknp V{:X}", x),
+            Instruction::Plane { n } => write!(f, "plane {}", n),
+            Instruction::Audio => write!(f, "audio"),
+            Instruction::PitchVx { x } => write!(f, "pitch V{:X}", x),
+            Instruction::LdVxDt { x } => write!(f, "ld V{:X} DT", x),
+            Instruction::LdVxK { x } => write!(f, "ld V{:X} K", x),
+            Instruction::LdDtVx { x } => write!(f, "ld DT V{:X}", x),
+            Instruction::LdStVx { x } => write!(f, "ld ST V{:X}", x),
+            Instruction::AddIVx { x } => write!(f, "add I V{:X}", x),
+            Instruction::LdFVx { x } => write!(f, "ld F V{:X}", x),
+            Instruction::LdHfVx { x } => write!(f, "ld HF V{:X}", x),
+            Instruction::LdBVx { x } => write!(f, "ld B V{:X}", x),
+            Instruction::LdIVx { x } => write!(f, "ld [I] V{:X}", x),
+            Instruction::LdVxI { x } => write!(f, "ld V{:X} [I]", x),
+            Instruction::LdRVx { x } => write!(f, "ld R V{:X}", x),
+            Instruction::LdVxR { x } => write!(f, "ld V{:X} R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Worked out from the opcode table independently of `decode`
+    fn is_valid(word: u16) -> bool {
+        let x = (word >> 8) & 0x0f;
+        let n = word & 0x000f;
+        let kk = word & 0x00ff;
+
+        match word >> 12 {
+            0x5 => n == 0 || n == 2 || n == 3,
+            0x8 => n <= 0x7 || n == 0xe,
+            0x9 => n == 0,
+            0xe => kk == 0x9e || kk == 0xa1,
+            0xf => {
+                match kk {
+                    0x00 | 0x02 => x == 0,
+                    0x01 | 0x07 | 0x0a | 0x15 | 0x18 | 0x1e | 0x29 | 0x30 | 0x33 | 0x3a |
+                    0x55 | 0x65 | 0x75 | 0x85 => true,
+                    _ => false,
+                }
+            }
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn decode_handles_every_word() {
+        for word in 0..=0xffff {
+            match decode(word) {
+                Ok(instruction) => {
+                    assert!(is_valid(word), "{:#06x} decoded as {:?}", word, instruction);
+                    assert!(!instruction.to_string().is_empty(), "{:#06x} has no text", word);
+                }
+                Err(e) => {
+                    assert!(!is_valid(word), "{:#06x} was rejected", word);
+                    assert_eq!(e.word, word);
+                    assert_eq!(e.to_string(), format!("Unrecognized opcode: {:#06x}", word));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_extracts_operands() {
+        assert_eq!(decode(0x00c7), Ok(Instruction::Scd { n: 7 }));
+        assert_eq!(decode(0x0123), Ok(Instruction::Sys(0x123)));
+        assert_eq!(decode(0x2abc), Ok(Instruction::Call(0xabc)));
+        assert_eq!(decode(0x5ab2), Ok(Instruction::SaveVxVy { x: 0xa, y: 0xb }));
+        assert_eq!(decode(0x8ab5), Ok(Instruction::SubVxVy { x: 0xa, y: 0xb }));
+        assert_eq!(decode(0xd12f), Ok(Instruction::Drw { x: 1, y: 2, n: 0xf }));
+        assert_eq!(decode(0xf301), Ok(Instruction::Plane { n: 3 }));
+        assert_eq!(decode(0xf465), Ok(Instruction::LdVxI { x: 4 }));
+    }
+
+    #[test]
+    fn decode_with_next_fills_in_long_addresses() {
+        assert_eq!(decode_with_next(0xf000, 0x1234), Ok(Instruction::LdILong(0x1234)));
+        assert_eq!(decode_with_next(0x6012, 0x1234), Ok(Instruction::LdVxByte { x: 0, kk: 0x12 }));
+        assert_eq!(Instruction::LdILong(0x1234).to_string(), "ld I long 0x1234");
+    }
+}
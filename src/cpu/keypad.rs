@@ -1,10 +1,58 @@
 use std::fmt;
 
+use super::error::EmuError;
+use super::savestate::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyWaitPolicy {
+    OnPress,
+    OnRelease,
+}
+
+impl KeyWaitPolicy {
+    pub fn from_name(name: &str) -> Option<KeyWaitPolicy> {
+        match name {
+            "press" => Some(KeyWaitPolicy::OnPress),
+            "release" => Some(KeyWaitPolicy::OnRelease),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            KeyWaitPolicy::OnPress => "press",
+            KeyWaitPolicy::OnRelease => "release",
+        }
+    }
+}
+
+impl Default for KeyWaitPolicy {
+    fn default() -> KeyWaitPolicy {
+        KeyWaitPolicy::OnRelease
+    }
+}
+
 #[derive(Default)]
 pub struct Keypad {
     pub keys: [bool; 16],
 }
 
+impl Keypad {
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        for key in self.keys.iter() {
+            writer.write_bool(*key);
+        }
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Keypad, EmuError> {
+        let mut keypad = Keypad::default();
+        for key in keypad.keys.iter_mut() {
+            *key = try!(reader.read_bool());
+        }
+        Ok(keypad)
+    }
+}
+
 impl fmt::Debug for Keypad {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for i in 0..15 {
@@ -0,0 +1,1322 @@
+use super::register::Registers;
+use super::keypad::{Keypad, KeyWaitPolicy};
+use super::memory::{self, Memory};
+use super::display::Display;
+use super::font::{FontLayout, FontSet};
+use super::instruction::{decode, Instruction};
+use super::error::{EmuError, FrameOutcome, StepOutcome};
+use super::breakpoint::{AccessKind, Breakpoints, Hit, MemoryAccess, Pending};
+use super::quirks::Quirks;
+use super::random::{Random, RandomMode};
+use super::rom::Rom;
+use super::savestate::{StateReader, StateWriter};
+use super::trace::{RegisterSnapshot, Tracer};
+
+use std::fmt;
+
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+const DEFAULT_AUDIO_PITCH: u8 = 64;
+
+// State of an in-progress Fx0A. `held` tracks keys that were already down when the
+// wait began, so that only a fresh press can satisfy it.
+#[derive(Clone, Copy)]
+struct KeyWait {
+    x: u8,
+    held: [bool; 16],
+    pressed: Option<u8>,
+}
+
+pub struct Machine {
+    reg: Registers,
+    mem: Memory,
+    keys: Keypad,
+    key_wait: Option<KeyWait>,
+    key_wait_policy: KeyWaitPolicy,
+    quirks: Quirks,
+    instructions_per_frame: usize,
+    display: Display,
+    display_updated: bool,
+    font_layout: FontLayout,
+    vblank_wait: bool,
+    // Instructions run so far in the current frame
+    frame_steps: usize,
+    // Frames completed since start-up, which movies use to time their input. It is not
+    // part of save states, so loading one doesn't move a recording back in time.
+    frames: u32,
+    breakpoints: Breakpoints,
+    // Set after stopping at a breakpoint so that the instruction there runs, rather
+    // than stopping again, when execution resumes
+    resuming: bool,
+    rpl_flags: [u8; 16],
+    exited: bool,
+    schip: bool,
+    xo_chip: bool,
+    audio_pattern: Option<[u8; 16]>,
+    audio_pitch: u8,
+    audio_updated: bool,
+    rng: Random,
+    tracer: Option<Tracer>,
+}
+
+impl fmt::Debug for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#?}{:#?}{:#?}", self.reg, self.mem, self.keys)
+    }
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine {
+            reg: Registers::new(),
+            mem: Memory::default(),
+            keys: Keypad::default(),
+            key_wait: None,
+            key_wait_policy: KeyWaitPolicy::default(),
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            display: Display::new(),
+            display_updated: false,
+            font_layout: FontLayout::default(),
+            vblank_wait: false,
+            frame_steps: 0,
+            frames: 0,
+            breakpoints: Breakpoints::default(),
+            resuming: false,
+            rpl_flags: [0; 16],
+            exited: false,
+            schip: false,
+            xo_chip: false,
+            audio_pattern: None,
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            audio_updated: false,
+            rng: Random::new(RandomMode::default(), 0),
+            tracer: None,
+        }
+    }
+
+    pub fn load_fonts(&mut self, fonts: &FontSet) -> Result<(), EmuError> {
+        self.font_layout = try!(self.mem.load_fonts(fonts));
+        Ok(())
+    }
+
+    // Copies the program into memory and starts execution at its load address
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), EmuError> {
+        try!(self.mem.load_rom(rom));
+        self.reg.write_pc(rom.load_addr());
+        Ok(())
+    }
+
+    pub fn set_key_wait_policy(&mut self, policy: KeyWaitPolicy) {
+        self.key_wait_policy = policy;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn set_random(&mut self, mode: RandomMode, seed: u32) {
+        self.rng = Random::new(mode, seed);
+    }
+
+    // Logs every instruction the tracer's filter accepts
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // Allows the SUPER-CHIP instructions, which are rejected otherwise
+    pub fn enable_schip(&mut self) {
+        self.schip = true;
+    }
+
+    // Switches to XO-CHIP: 64 KiB of memory and the extended instruction set, which
+    // includes SUPER-CHIP's
+    pub fn enable_xo_chip(&mut self) {
+        self.schip = true;
+        self.xo_chip = true;
+        self.mem.resize(memory::XO_CHIP_MEM_SIZE);
+    }
+
+    pub fn step(&mut self) -> Result<StepOutcome, EmuError> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
+        }
+
+        if let Some(mut wait) = self.key_wait.take() {
+            match self.poll_key_wait(&mut wait) {
+                Some(key) => self.reg.write_register(wait.x, key),
+                None => {
+                    self.key_wait = Some(wait);
+                    return Ok(StepOutcome::WaitingForKey);
+                }
+            }
+        }
+
+        let pc = self.reg.read_pc();
+        let word = try!(self.read_word());
+
+        let mut instruction = try!(decode(word).map_err(|e| {
+            EmuError::InvalidOpcode {
+                pc: pc,
+                word: e.word,
+            }
+        }));
+
+        if (instruction.is_schip() && !self.schip) || (instruction.is_xo_chip() && !self.xo_chip) {
+            return Err(EmuError::InvalidOpcode {
+                pc: pc,
+                word: word,
+            });
+        }
+
+        if let Instruction::LdILong(_) = instruction {
+            instruction = Instruction::LdILong(try!(self.read_word()));
+        }
+
+        let before = match self.tracer {
+            Some(ref tracer) if tracer.wants(pc, word) => Some(RegisterSnapshot::of(&self.reg)),
+            _ => None,
+        };
+
+        try!(self.execute(&instruction));
+
+        if let (Some(before), Some(tracer)) = (before, self.tracer.as_mut()) {
+            let after = RegisterSnapshot::of(&self.reg);
+            try!(tracer.record(pc, word, &instruction, &before, &after));
+        }
+
+        Ok(StepOutcome::Executed(instruction))
+    }
+
+    // Runs one 60 Hz frame: a batch of instructions followed by a single timer tick
+    pub fn run_frame(&mut self) -> Result<FrameOutcome, EmuError> {
+        self.run_frame_until(|_| false)
+    }
+
+    // Like `run_frame`, but returns early at a breakpoint or once `stop` holds, both of
+    // which are checked before each instruction. The next call finishes the frame.
+    pub fn run_frame_until<P>(&mut self, mut stop: P) -> Result<FrameOutcome, EmuError>
+        where P: FnMut(&Machine) -> bool
+    {
+        loop {
+            if let Some(hit) = self.next_breakpoint_hit() {
+                return Ok(FrameOutcome::Breakpoint(hit));
+            }
+            if stop(self) {
+                return Ok(FrameOutcome::Stopped);
+            }
+
+            try!(self.step_in_frame());
+            if self.frame_steps == 0 {
+                return Ok(FrameOutcome::Completed);
+            }
+        }
+    }
+
+    // Checks the breakpoints against the instruction at PC. A hit is reported once;
+    // the instruction then runs on the next step.
+    pub fn next_breakpoint_hit(&mut self) -> Option<Hit> {
+        // Nothing runs while Fx0A waits, so there is nothing to check
+        if self.resuming || self.key_wait.is_some() {
+            return None;
+        }
+
+        let hit = self.check_breakpoints();
+        self.resuming = hit.is_some();
+        hit
+    }
+
+    // Runs a single instruction, ending the frame and ticking the timers once the
+    // frame has used up its instructions
+    pub fn step_in_frame(&mut self) -> Result<StepOutcome, EmuError> {
+        let outcome = try!(self.step());
+        self.frame_steps += 1;
+        self.resuming = false;
+
+        if self.vblank_wait || outcome == StepOutcome::Exited ||
+           self.frame_steps >= self.instructions_per_frame {
+            self.frame_steps = 0;
+            self.frames = self.frames.wrapping_add(1);
+            self.vblank_wait = false;
+            self.tick_timers();
+            self.rng.tick();
+            if let Some(ref mut tracer) = self.tracer {
+                try!(tracer.end_frame());
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        self.reg.save_state(&mut writer);
+        self.mem.save_state(&mut writer);
+        self.keys.save_state(&mut writer);
+        self.quirks.save_state(&mut writer);
+        self.display.save_state(&mut writer);
+
+        match self.key_wait {
+            Some(ref wait) => {
+                writer.write_bool(true);
+                writer.write_u8(wait.x);
+                for held in wait.held.iter() {
+                    writer.write_bool(*held);
+                }
+                writer.write_bool(wait.pressed.is_some());
+                writer.write_u8(wait.pressed.unwrap_or(0));
+            }
+            None => writer.write_bool(false),
+        }
+
+        writer.write_bytes(&self.rpl_flags);
+        writer.write_bool(self.exited);
+        writer.write_bool(self.schip);
+        writer.write_bool(self.xo_chip);
+        writer.write_bool(self.audio_pattern.is_some());
+        writer.write_bytes(&self.audio_pattern.unwrap_or([0; 16]));
+        writer.write_u8(self.audio_pitch);
+        self.rng.save_state(&mut writer);
+
+        writer.into_bytes()
+    }
+
+    // Restores a snapshot taken by `save_state`. Nothing is modified unless the whole
+    // snapshot is valid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let mut reader = try!(StateReader::new(data));
+
+        let reg = try!(Registers::load_state(&mut reader));
+        let mem = try!(Memory::load_state(&mut reader, self.mem.size()));
+        let keys = try!(Keypad::load_state(&mut reader));
+        let quirks = try!(Quirks::load_state(&mut reader));
+        let display = try!(Display::load_state(&mut reader));
+
+        let key_wait = if try!(reader.read_bool()) {
+            let x = try!(reader.read_u8()) & 0x0f;
+            let mut held = [false; 16];
+            for key in held.iter_mut() {
+                *key = try!(reader.read_bool());
+            }
+            let has_pressed = try!(reader.read_bool());
+            let pressed = try!(reader.read_u8()) & 0x0f;
+            Some(KeyWait {
+                x: x,
+                held: held,
+                pressed: if has_pressed { Some(pressed) } else { None },
+            })
+        } else {
+            None
+        };
+
+        let mut rpl_flags = [0u8; 16];
+        rpl_flags.copy_from_slice(try!(reader.read_bytes(16)));
+        let exited = try!(reader.read_bool());
+        let schip = try!(reader.read_bool());
+        let xo_chip = try!(reader.read_bool());
+        if schip != self.schip || xo_chip != self.xo_chip {
+            return Err(EmuError::InvalidSaveState("saved on another platform".to_string()));
+        }
+        let has_audio_pattern = try!(reader.read_bool());
+        let mut audio_pattern = [0u8; 16];
+        audio_pattern.copy_from_slice(try!(reader.read_bytes(16)));
+        let audio_pitch = try!(reader.read_u8());
+        let rng = try!(Random::load_state(&mut reader));
+        try!(reader.finish());
+
+        self.reg = reg;
+        self.mem = mem;
+        self.keys = keys;
+        self.quirks = quirks;
+        self.display = display;
+        self.key_wait = key_wait;
+        self.rpl_flags = rpl_flags;
+        self.exited = exited;
+        self.audio_pattern = if has_audio_pattern {
+            Some(audio_pattern)
+        } else {
+            None
+        };
+        self.audio_pitch = audio_pitch;
+        self.rng = rng;
+        self.vblank_wait = false;
+        self.frame_steps = 0;
+        self.resuming = false;
+        self.display_updated = true;
+        self.audio_updated = true;
+
+        Ok(())
+    }
+
+    pub fn tick_timers(&mut self) {
+        let delay_timer_value = self.reg.read_delay_timer();
+        if delay_timer_value > 0 {
+            self.reg.write_delay_timer(delay_timer_value - 1);
+        }
+
+        let sound_timer_value = self.reg.read_sound_timer();
+        if sound_timer_value > 0 {
+            self.reg.write_sound_timer(sound_timer_value - 1);
+        }
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.reg.read_sound_timer() > 0
+    }
+
+    pub fn framebuffer(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn audio_pitch(&self) -> u8 {
+        self.audio_pitch
+    }
+
+    pub fn audio_updated(&self) -> bool {
+        self.audio_updated
+    }
+
+    pub fn clear_audio_updated(&mut self) {
+        self.audio_updated = false;
+    }
+
+    pub fn display_updated(&self) -> bool {
+        self.display_updated
+    }
+
+    pub fn clear_display_updated(&mut self) {
+        self.display_updated = false;
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.reg
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.reg
+    }
+
+    pub fn read_memory(&self, addr: u16) -> Option<u8> {
+        self.mem.read_byte(addr).ok()
+    }
+
+    pub fn write_memory(&mut self, addr: u16, byte: u8) -> Result<(), EmuError> {
+        self.mem.write_byte(addr, byte)
+    }
+
+    pub fn schip(&self) -> bool {
+        self.schip
+    }
+
+    pub fn xo_chip(&self) -> bool {
+        self.xo_chip
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn key_wait_policy(&self) -> KeyWaitPolicy {
+        self.key_wait_policy
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.keys.keys[key as usize] = pressed;
+    }
+
+    pub fn _dump_mem_to_disk(&self) -> Result<(), EmuError> {
+        self.mem._dump_mem_to_disk()
+    }
+
+    pub fn _debug_pong_rom(&self) {
+        self.mem._display_pong_rom();
+    }
+
+    pub fn _debug_font_data(&self) {
+        self.mem._display_font_data();
+    }
+
+    fn poll_key_wait(&self, wait: &mut KeyWait) -> Option<u8> {
+        if let Some(key) = wait.pressed {
+            if self.keys.keys[key as usize] {
+                return None;
+            }
+            return Some(key);
+        }
+
+        for key in 0..16 {
+            if !self.keys.keys[key] {
+                wait.held[key] = false;
+            } else if !wait.held[key] {
+                match self.key_wait_policy {
+                    KeyWaitPolicy::OnPress => return Some(key as u8),
+                    KeyWaitPolicy::OnRelease => {
+                        wait.pressed = Some(key as u8);
+                        return None;
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn check_breakpoints(&mut self) -> Option<Hit> {
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+
+        let word = self.peek_word().ok();
+        let accesses = self.pending_accesses(word);
+        let pending = Pending {
+            reg: &self.reg,
+            mem: &self.mem,
+            word: word,
+            accesses: &accesses,
+        };
+        self.breakpoints.check(&pending)
+    }
+
+    // The memory the instruction at PC is about to touch, worked out before it runs so
+    // that watchpoints stop ahead of the access
+    fn pending_accesses(&self, word: Option<u16>) -> Vec<MemoryAccess> {
+        let pc = self.reg.read_pc();
+        let word = match word {
+            Some(word) => word,
+            None => return Vec::new(),
+        };
+
+        let fetch_len = if self.xo_chip && word == 0xf000 { 4 } else { 2 };
+        let mut accesses = vec![MemoryAccess {
+                                    kind: AccessKind::Execute,
+                                    addr: pc,
+                                    len: fetch_len,
+                                }];
+
+        let data = match decode(word) {
+            Ok(Instruction::SaveVxVy { x, y }) => Some((AccessKind::Write, register_range(x, y).len())),
+            Ok(Instruction::LoadVxVy { x, y }) => Some((AccessKind::Read, register_range(x, y).len())),
+            Ok(Instruction::Drw { n, .. }) => {
+                let sprite_len = match n {
+                    0 if self.schip => 32,
+                    _ => n as usize,
+                };
+                let planes = (self.display.planes() & 0x3).count_ones() as usize;
+                match sprite_len * planes {
+                    0 => None,
+                    len => Some((AccessKind::Read, len)),
+                }
+            }
+            Ok(Instruction::Audio) => Some((AccessKind::Read, 16)),
+            Ok(Instruction::LdBVx { .. }) => Some((AccessKind::Write, 3)),
+            Ok(Instruction::LdIVx { x }) => Some((AccessKind::Write, x as usize + 1)),
+            Ok(Instruction::LdVxI { x }) => Some((AccessKind::Read, x as usize + 1)),
+            _ => None,
+        };
+
+        if let Some((kind, len)) = data {
+            accesses.push(MemoryAccess {
+                kind: kind,
+                addr: self.reg.read_register_i(),
+                len: len,
+            });
+        }
+
+        accesses
+    }
+
+    fn read_word(&mut self) -> Result<u16, EmuError> {
+        let instruction = try!(self.peek_word());
+        self.reg.increment_pc();
+        Ok(instruction)
+    }
+
+    fn peek_word(&self) -> Result<u16, EmuError> {
+        let pc = self.reg.read_pc();
+        let instruction_high_order = (try!(self.mem.read_byte(pc)) as u16) << 8;
+        let instruction_low_order = try!(self.mem.read_byte(pc.wrapping_add(1))) as u16;
+
+        Ok(instruction_high_order | instruction_low_order)
+    }
+
+    // On XO-CHIP a skip must step over both words of a long `F000 nnnn` load
+    fn skip_next_instruction(&mut self) -> Result<(), EmuError> {
+        if self.xo_chip && try!(self.peek_word()) == 0xf000 {
+            self.reg.increment_pc();
+        }
+        self.reg.increment_pc();
+        Ok(())
+    }
+
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), EmuError> {
+        match *instruction {
+            Instruction::Sys(_) => {
+                // we will ignore the 0nnn opcode used for jumping to machine code routines
+            }
+            Instruction::Cls => {
+                self.display.clear();
+                self.display_updated = true;
+            }
+            Instruction::Ret => {
+                try!(self.reg.return_from_subroutine());
+            }
+            Instruction::Scd { n } => {
+                self.display.scroll_down(n as usize);
+                self.display_updated = true;
+            }
+            Instruction::Scu { n } => {
+                self.display.scroll_up(n as usize);
+                self.display_updated = true;
+            }
+            Instruction::Scr => {
+                self.display.scroll_right(4);
+                self.display_updated = true;
+            }
+            Instruction::Scl => {
+                self.display.scroll_left(4);
+                self.display_updated = true;
+            }
+            Instruction::Exit => {
+                self.exited = true;
+            }
+            Instruction::Low => {
+                self.display.set_hires(false);
+                self.display_updated = true;
+            }
+            Instruction::High => {
+                self.display.set_hires(true);
+                self.display_updated = true;
+            }
+            Instruction::Jp(addr) => {
+                try!(self.reg.jump_to_address(addr, JumpType::NORMAL));
+            }
+            Instruction::Call(addr) => {
+                try!(self.reg.jump_to_address(addr, JumpType::SUBROUTINE));
+            }
+            Instruction::SeVxByte { x, kk } => {
+                if self.reg.read_register(x) == kk {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::SneVxByte { x, kk } => {
+                if self.reg.read_register(x) != kk {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::SeVxVy { x, y } => {
+                if self.reg.read_register(x) == self.reg.read_register(y) {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::SaveVxVy { x, y } => {
+                let mem_addr = self.reg.read_register_i();
+                for (offset, n) in register_range(x, y).into_iter().enumerate() {
+                    let byte = self.reg.read_register(n);
+                    try!(self.mem.write_byte(mem_addr.wrapping_add(offset as u16), byte));
+                }
+            }
+            Instruction::LoadVxVy { x, y } => {
+                let mem_addr = self.reg.read_register_i();
+                for (offset, n) in register_range(x, y).into_iter().enumerate() {
+                    let byte = try!(self.mem.read_byte(mem_addr.wrapping_add(offset as u16)));
+                    self.reg.write_register(n, byte);
+                }
+            }
+            Instruction::LdVxByte { x, kk } => {
+                self.reg.write_register(x, kk);
+            }
+            Instruction::AddVxByte { x, kk } => {
+                let data_value = self.reg.read_register(x).wrapping_add(kk);
+                self.reg.write_register(x, data_value);
+            }
+            Instruction::LdVxVy { x, y } => {
+                let data_value = self.reg.read_register(y);
+                self.reg.write_register(x, data_value);
+            }
+            Instruction::OrVxVy { x, y } => {
+                let data_value = self.reg.read_register(x) | self.reg.read_register(y);
+                self.reg.write_register(x, data_value);
+                if self.quirks.logic_resets_vf {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::AndVxVy { x, y } => {
+                let data_value = self.reg.read_register(x) & self.reg.read_register(y);
+                self.reg.write_register(x, data_value);
+                if self.quirks.logic_resets_vf {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::XorVxVy { x, y } => {
+                let data_value = self.reg.read_register(x) ^ self.reg.read_register(y);
+                self.reg.write_register(x, data_value);
+                if self.quirks.logic_resets_vf {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::AddVxVy { x, y } => {
+                let reg_one_value = self.reg.read_register(x);
+                let reg_two_value = self.reg.read_register(y);
+
+                let result: u32 = (reg_one_value as u32) + (reg_two_value as u32);
+
+                // VF is written last so that it wins when x is F
+                self.reg.write_register(x, result as u8);
+                if result > 255 {
+                    self.reg.set_vf();
+                } else {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::SubVxVy { x, y } => {
+                let reg_one_value = self.reg.read_register(x);
+                let reg_two_value = self.reg.read_register(y);
+
+                // VF is set when there is no borrow
+                self.reg.write_register(x, reg_one_value.wrapping_sub(reg_two_value));
+                if reg_one_value >= reg_two_value {
+                    self.reg.set_vf();
+                } else {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::ShrVxVy { x, y } => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let reg_one_value = self.reg.read_register(source);
+
+                self.reg.write_register(x, reg_one_value >> 1);
+                if (reg_one_value & 1) == 1 {
+                    self.reg.set_vf();
+                } else {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::SubnVxVy { x, y } => {
+                let reg_one_value = self.reg.read_register(x);
+                let reg_two_value = self.reg.read_register(y);
+
+                self.reg.write_register(x, reg_two_value.wrapping_sub(reg_one_value));
+                if reg_two_value >= reg_one_value {
+                    self.reg.set_vf();
+                } else {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::ShlVxVy { x, y } => {
+                let source = if self.quirks.shift_uses_vy { y } else { x };
+                let reg_one_value = self.reg.read_register(source);
+
+                self.reg.write_register(x, reg_one_value << 1);
+                if ((reg_one_value >> 7) & 1) == 1 {
+                    self.reg.set_vf();
+                } else {
+                    self.reg.clear_vf();
+                }
+            }
+            Instruction::SneVxVy { x, y } => {
+                if self.reg.read_register(x) != self.reg.read_register(y) {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::LdIAddr(addr) => {
+                self.reg.write_register_i(addr);
+            }
+            Instruction::LdILong(addr) => {
+                self.reg.write_register_i(addr);
+            }
+            Instruction::JpV0(addr) => {
+                let offset_reg = if self.quirks.jump_uses_vx {
+                    ((addr >> 8) & 0x0f) as u8
+                } else {
+                    0
+                };
+                let offset = self.reg.read_register(offset_reg) as u16;
+                try!(self.reg.jump_to_address(addr + offset, JumpType::NORMAL));
+            }
+            Instruction::RndVxByte { x, kk } => {
+                let rand_num = self.rng.next_byte(self.mem.interpreter_page());
+                self.reg.write_register(x, kk & rand_num);
+            }
+            Instruction::Drw { x, y, n } => {
+                let width = self.display.width();
+                let height = self.display.height();
+                let sprite_x = (self.reg.read_register(x) as usize) % width;
+                let sprite_y = (self.reg.read_register(y) as usize) % height;
+                let sprite_addr = self.reg.read_register_i();
+
+                // Dxy0 draws a 16x16 SUPER-CHIP sprite stored as two bytes per row. On
+                // plain CHIP-8 it is a sprite with no rows, so it only clears VF.
+                let (rows, bytes_per_row) = match n {
+                    0 if self.schip => (16, 2),
+                    _ => (n as usize, 1),
+                };
+
+                self.reg.clear_vf();
+
+                // With both XO-CHIP planes selected, the second plane's sprite data
+                // follows directly after the first's
+                let mut plane_addr = sprite_addr;
+                for plane in [1u8, 2u8].iter().cloned() {
+                    if self.display.planes() & plane == 0 {
+                        continue;
+                    }
+
+                    for row in 0..rows {
+                        if self.quirks.clip_sprites && sprite_y + row >= height {
+                            break;
+                        }
+                        let y_index = (sprite_y + row) % height;
+
+                        for column_byte in 0..bytes_per_row {
+                            let byte_offset = (row * bytes_per_row + column_byte) as u16;
+                            let byte = try!(self.mem.read_byte(plane_addr.wrapping_add(byte_offset)));
+
+                            for i in 0..8 {
+                                if (byte >> (7 - i)) & 1 == 0 {
+                                    continue;
+                                }
+
+                                let column = column_byte * 8 + i;
+                                if self.quirks.clip_sprites && sprite_x + column >= width {
+                                    continue;
+                                }
+
+                                let x_index = (sprite_x + column) % width;
+                                if self.display.toggle(x_index, y_index, plane) {
+                                    self.reg.set_vf();
+                                }
+                            }
+                        }
+                    }
+
+                    plane_addr = plane_addr.wrapping_add((rows * bytes_per_row) as u16);
+                }
+
+                self.display_updated = true;
+                self.vblank_wait = self.quirks.display_wait;
+            }
+            Instruction::SkpVx { x } => {
+                let key = self.reg.read_register(x) & 0x0f;
+                if self.keys.keys[key as usize] == true {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::SknpVx { x } => {
+                let key = self.reg.read_register(x) & 0x0f;
+                if self.keys.keys[key as usize] == false {
+                    try!(self.skip_next_instruction());
+                }
+            }
+            Instruction::Plane { n } => {
+                self.display.select_planes(n);
+            }
+            Instruction::Audio => {
+                let mem_addr = self.reg.read_register_i();
+                let mut pattern = [0u8; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = try!(self.mem.read_byte(mem_addr.wrapping_add(offset as u16)));
+                }
+                self.audio_pattern = Some(pattern);
+                self.audio_updated = true;
+            }
+            Instruction::PitchVx { x } => {
+                self.audio_pitch = self.reg.read_register(x);
+                self.audio_updated = true;
+            }
+            Instruction::LdVxDt { x } => {
+                let reg_value = self.reg.read_delay_timer();
+                self.reg.write_register(x, reg_value);
+            }
+            Instruction::LdVxK { x } => {
+                self.key_wait = Some(KeyWait {
+                    x: x,
+                    held: self.keys.keys,
+                    pressed: None,
+                });
+            }
+            Instruction::LdDtVx { x } => {
+                let reg_value = self.reg.read_register(x);
+                self.reg.write_delay_timer(reg_value);
+            }
+            Instruction::LdStVx { x } => {
+                let reg_value = self.reg.read_register(x);
+                self.reg.write_sound_timer(reg_value);
+            }
+            Instruction::AddIVx { x } => {
+                let reg_value = self.reg.read_register(x) as u16;
+                let i_value = self.reg.read_register_i();
+                self.reg.write_register_i(i_value.wrapping_add(reg_value));
+            }
+            Instruction::LdFVx { x } => {
+                let glyph = self.font_layout.small_glyph(self.reg.read_register(x));
+                self.reg.write_register_i(glyph);
+            }
+            Instruction::LdHfVx { x } => {
+                let glyph = self.font_layout.big_glyph(self.reg.read_register(x));
+                self.reg.write_register_i(glyph);
+            }
+            Instruction::LdBVx { x } => {
+                let mut reg_value = self.reg.read_register(x);
+                let ones_digit: u8 = reg_value % 10;
+                reg_value = reg_value / 10;
+                let tens_digit: u8 = reg_value % 10;
+                reg_value = reg_value / 10;
+                let hundreds_digit: u8 = reg_value % 10;
+
+                let mem_addr = self.reg.read_register_i();
+                try!(self.mem.write_byte(mem_addr, hundreds_digit));
+                try!(self.mem.write_byte(mem_addr.wrapping_add(1), tens_digit));
+                try!(self.mem.write_byte(mem_addr.wrapping_add(2), ones_digit));
+            }
+            Instruction::LdIVx { x } => {
+                let mem_addr = self.reg.read_register_i();
+                for n in 0..(x + 1) {
+                    let byte = self.reg.read_register(n);
+                    try!(self.mem.write_byte(mem_addr.wrapping_add(n as u16), byte));
+                }
+                if self.quirks.load_store_increments_i {
+                    self.reg.write_register_i(mem_addr.wrapping_add(x as u16 + 1));
+                }
+            }
+            Instruction::LdVxI { x } => {
+                let mem_addr = self.reg.read_register_i();
+                for n in 0..(x + 1) {
+                    let byte = try!(self.mem.read_byte(mem_addr.wrapping_add(n as u16)));
+                    self.reg.write_register(n, byte);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.reg.write_register_i(mem_addr.wrapping_add(x as u16 + 1));
+                }
+            }
+            Instruction::LdRVx { x } => {
+                for n in 0..(x + 1) {
+                    self.rpl_flags[n as usize] = self.reg.read_register(n);
+                }
+            }
+            Instruction::LdVxR { x } => {
+                for n in 0..(x + 1) {
+                    let flag = self.rpl_flags[n as usize];
+                    self.reg.write_register(n, flag);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Registers named by 5xy2/5xy3, which may run in either direction
+fn register_range(x: u8, y: u8) -> Vec<u8> {
+    if x <= y {
+        (x..(y + 1)).collect()
+    } else {
+        (y..(x + 1)).rev().collect()
+    }
+}
+
+pub enum JumpType {
+    NORMAL,
+    SUBROUTINE,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::breakpoint::Trigger;
+
+    fn machine_with(program: &[u8]) -> Machine {
+        let mut machine = Machine::new();
+        machine.load_rom(&Rom::from_bytes(program)).unwrap();
+        machine
+    }
+
+    // ld V0 5; ld DT V0; loop: add V1 1; jp loop
+    const COUNTER: [u8; 8] = [0x60, 0x05, 0xf0, 0x15, 0x71, 0x01, 0x12, 0x04];
+
+    #[test]
+    fn step_executes_one_instruction() {
+        let mut machine = machine_with(&COUNTER);
+
+        let outcome = machine.step().unwrap();
+        assert_eq!(outcome, StepOutcome::Executed(Instruction::LdVxByte { x: 0, kk: 5 }));
+        assert_eq!(machine.registers().read_register(0), 5);
+        assert_eq!(machine.registers().read_pc(), 0x202);
+    }
+
+    #[test]
+    fn step_rejects_invalid_opcodes() {
+        let mut machine = machine_with(&[0x80, 0x08]);
+
+        match machine.step() {
+            Err(EmuError::InvalidOpcode { pc: 0x200, word: 0x8008 }) => {}
+            other => panic!("expected an invalid opcode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn step_only_runs_super_chip_instructions_when_enabled() {
+        // high; exit
+        let program = [0x00, 0xff, 0x00, 0xfd];
+
+        let mut machine = machine_with(&program);
+        match machine.step() {
+            Err(EmuError::InvalidOpcode { pc: 0x200, word: 0x00ff }) => {}
+            other => panic!("expected an invalid opcode, got {:?}", other),
+        }
+
+        let mut machine = machine_with(&program);
+        machine.enable_schip();
+        assert_eq!(machine.step().unwrap(), StepOutcome::Executed(Instruction::High));
+        assert_eq!(machine.framebuffer().width(), 128);
+    }
+
+    #[test]
+    fn step_fetches_the_address_of_long_loads() {
+        let mut machine = machine_with(&[0xf0, 0x00, 0x12, 0x34]);
+        machine.enable_xo_chip();
+
+        assert_eq!(machine.step().unwrap(), StepOutcome::Executed(Instruction::LdILong(0x1234)));
+        assert_eq!(machine.registers().read_register_i(), 0x1234);
+        assert_eq!(machine.registers().read_pc(), 0x204);
+    }
+
+    #[test]
+    fn run_frame_runs_a_frame_of_instructions_then_ticks_timers() {
+        let mut machine = machine_with(&COUNTER);
+
+        assert_eq!(machine.run_frame().unwrap(), FrameOutcome::Completed);
+        // Two setup instructions, then four trips round the loop
+        assert_eq!(machine.registers().read_register(1), 4);
+        assert_eq!(machine.registers().read_delay_timer(), 4);
+    }
+
+    #[test]
+    fn run_frame_until_stops_before_the_instruction_and_finishes_the_frame_later() {
+        let mut machine = machine_with(&COUNTER);
+
+        let outcome = machine.run_frame_until(|m| m.registers().read_pc() == 0x204).unwrap();
+        assert_eq!(outcome, FrameOutcome::Stopped);
+        assert_eq!(machine.registers().read_pc(), 0x204);
+        assert_eq!(machine.registers().read_delay_timer(), 5);
+        assert_eq!(machine.frames(), 0);
+
+        assert_eq!(machine.run_frame().unwrap(), FrameOutcome::Completed);
+        assert_eq!(machine.registers().read_register(1), 4);
+        assert_eq!(machine.registers().read_delay_timer(), 4);
+        assert_eq!(machine.frames(), 1);
+    }
+
+    #[test]
+    fn run_frame_until_stops_at_breakpoints_once() {
+        let mut machine = machine_with(&COUNTER);
+        let id = machine.breakpoints_mut().add(Trigger::Address(0x206), None);
+
+        match machine.run_frame_until(|_| false).unwrap() {
+            FrameOutcome::Breakpoint(hit) => {
+                assert_eq!(hit.id, id);
+                assert_eq!(hit.pc, 0x206);
+            }
+            other => panic!("expected a breakpoint, got {:?}", other),
+        }
+        assert_eq!(machine.registers().read_register(1), 1);
+
+        // Resuming runs the instruction at the breakpoint before checking again
+        machine.run_frame_until(|_| false).unwrap();
+        assert_eq!(machine.registers().read_register(1), 2);
+        assert_eq!(machine.breakpoints().get(id).unwrap().hits, 2);
+    }
+
+    // Runs `program` after setting V0, V1 and VF, returning V0 and VF
+    fn alu(v0: u8, v1: u8, program: &[u8]) -> (u8, u8) {
+        let mut setup = vec![0x60, v0, 0x61, v1, 0x6f, v1];
+        setup.extend_from_slice(program);
+        let mut machine = machine_with(&setup);
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+        (machine.registers().read_register(0), machine.registers().read_register(0xf))
+    }
+
+    #[test]
+    fn sub_sets_vf_unless_it_borrows() {
+        assert_eq!(alu(5, 3, &[0x80, 0x15]), (2, 1));
+        assert_eq!(alu(5, 5, &[0x80, 0x15]), (0, 1));
+        assert_eq!(alu(3, 5, &[0x80, 0x15]), (0xfe, 0));
+        assert_eq!(alu(5, 3, &[0x80, 0x17]), (0xfe, 0));
+        assert_eq!(alu(5, 5, &[0x80, 0x17]), (0, 1));
+        assert_eq!(alu(3, 5, &[0x80, 0x17]), (2, 1));
+    }
+
+    #[test]
+    fn arithmetic_on_vf_keeps_the_flag() {
+        // VF starts out equal to V1, so each case writes VF as both result and flag
+        let flag_of = |program: &[u8]| alu(0, 0x81, program).1;
+        assert_eq!(flag_of(&[0x8f, 0x14]), 1);
+        assert_eq!(flag_of(&[0x8f, 0x15]), 1);
+        assert_eq!(flag_of(&[0x8f, 0x17]), 1);
+        assert_eq!(flag_of(&[0x8f, 0xf6]), 1);
+        assert_eq!(flag_of(&[0x8f, 0xfe]), 1);
+    }
+
+    #[test]
+    fn load_state_restores_a_saved_state() {
+        let mut machine = machine_with(&COUNTER);
+        machine.run_frame().unwrap();
+        let saved = machine.save_state();
+
+        machine.run_frame().unwrap();
+        machine.write_memory(0x300, 0xaa).unwrap();
+        assert!(machine.save_state() != saved);
+
+        machine.load_state(&saved).unwrap();
+        assert_eq!(machine.save_state(), saved);
+        assert_eq!(machine.registers().read_register(1), 4);
+        assert_eq!(machine.read_memory(0x300), Some(0));
+    }
+
+    #[test]
+    fn load_state_leaves_the_machine_alone_on_bad_data() {
+        let mut machine = machine_with(&COUNTER);
+        machine.run_frame().unwrap();
+        let saved = machine.save_state();
+
+        assert!(machine.load_state(&saved[..saved.len() - 1]).is_err());
+        assert!(machine.load_state(b"not a state").is_err());
+        assert_eq!(machine.save_state(), saved);
+    }
+
+    // ld V0 K; loop: jp loop
+    const WAIT_FOR_KEY: [u8; 4] = [0xf0, 0x0a, 0x12, 0x02];
+
+    fn waiting_machine(policy: KeyWaitPolicy) -> Machine {
+        let mut machine = machine_with(&WAIT_FOR_KEY);
+        machine.set_key_wait_policy(policy);
+        machine.step().unwrap();
+        machine
+    }
+
+    #[test]
+    fn key_wait_on_release_resumes_once_the_key_comes_up() {
+        let mut machine = waiting_machine(KeyWaitPolicy::OnRelease);
+
+        machine.set_key(0x5, true);
+        assert_eq!(machine.step().unwrap(), StepOutcome::WaitingForKey);
+        assert_eq!(machine.step().unwrap(), StepOutcome::WaitingForKey);
+
+        machine.set_key(0x5, false);
+        assert!(machine.step().unwrap() != StepOutcome::WaitingForKey);
+        assert_eq!(machine.registers().read_register(0), 0x5);
+    }
+
+    #[test]
+    fn key_wait_on_press_resumes_as_soon_as_the_key_goes_down() {
+        let mut machine = waiting_machine(KeyWaitPolicy::OnPress);
+        assert_eq!(machine.step().unwrap(), StepOutcome::WaitingForKey);
+
+        machine.set_key(0x5, true);
+        assert!(machine.step().unwrap() != StepOutcome::WaitingForKey);
+        assert_eq!(machine.registers().read_register(0), 0x5);
+    }
+
+    #[test]
+    fn key_wait_ignores_keys_held_when_it_started() {
+        for &policy in [KeyWaitPolicy::OnPress, KeyWaitPolicy::OnRelease].iter() {
+            let mut machine = machine_with(&WAIT_FOR_KEY);
+            machine.set_key_wait_policy(policy);
+            machine.set_key(0x3, true);
+            machine.step().unwrap();
+
+            assert_eq!(machine.step().unwrap(), StepOutcome::WaitingForKey);
+            machine.set_key(0x3, false);
+            assert_eq!(machine.step().unwrap(), StepOutcome::WaitingForKey);
+
+            // Pressed again, it is a fresh press
+            machine.set_key(0x3, true);
+            machine.step().unwrap();
+            machine.set_key(0x3, false);
+            machine.step().unwrap();
+            assert_eq!(machine.registers().read_register(0), 0x3);
+            assert!(machine.step().unwrap() != StepOutcome::WaitingForKey);
+        }
+    }
+
+    #[test]
+    fn timers_keep_ticking_during_a_key_wait() {
+        let mut machine = waiting_machine(KeyWaitPolicy::OnRelease);
+        machine.registers_mut().write_delay_timer(10);
+        machine.registers_mut().write_sound_timer(5);
+
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+        }
+
+        assert_eq!(machine.registers().read_delay_timer(), 7);
+        assert_eq!(machine.registers().read_sound_timer(), 2);
+        assert_eq!(machine.registers().read_pc(), 0x202);
+    }
+
+    // ld I sprite; ld V0 8; ld VF 1; drw V0 V0 0; sprite: 16 rows of two bytes
+    fn dxy0_machine() -> Machine {
+        let mut program = vec![0xa2, 0x08, 0x60, 0x08, 0x6f, 0x01, 0xd0, 0x00];
+        program.extend_from_slice(&[0xff; 32]);
+        machine_with(&program)
+    }
+
+    fn lit_pixels(machine: &Machine) -> usize {
+        let display = machine.framebuffer();
+        let mut lit = 0;
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                if display.pixel(x, y) != 0 {
+                    lit += 1;
+                }
+            }
+        }
+        lit
+    }
+
+    #[test]
+    fn dxy0_draws_nothing_on_chip8() {
+        let mut machine = dxy0_machine();
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+
+        assert_eq!(lit_pixels(&machine), 0);
+        assert_eq!(machine.registers().read_register(0xf), 0);
+        assert_eq!(machine.registers().read_pc(), 0x208);
+    }
+
+    #[test]
+    fn dxy0_draws_a_16x16_sprite_on_super_chip() {
+        let mut machine = dxy0_machine();
+        machine.enable_schip();
+        for _ in 0..4 {
+            machine.step().unwrap();
+        }
+
+        assert_eq!(lit_pixels(&machine), 16 * 16);
+        assert_eq!(machine.registers().read_register(0xf), 0);
+    }
+
+    #[test]
+    fn load_state_rejects_states_from_another_platform() {
+        let chip8 = machine_with(&COUNTER).save_state();
+        let mut xo_chip = machine_with(&COUNTER);
+        xo_chip.enable_xo_chip();
+        let xo_chip_state = xo_chip.save_state();
+
+        match xo_chip.load_state(&chip8) {
+            Err(EmuError::InvalidSaveState(_)) => {}
+            other => panic!("expected an invalid save state, got {:?}", other),
+        }
+        assert_eq!(xo_chip.save_state(), xo_chip_state);
+
+        let mut machine = machine_with(&COUNTER);
+        assert!(machine.load_state(&xo_chip_state).is_err());
+        assert_eq!(machine.save_state(), chip8);
+
+        let mut schip = machine_with(&COUNTER);
+        schip.enable_schip();
+        assert!(schip.load_state(&chip8).is_err());
+    }
+
+
+    // loop: rnd V0 0xff; jp loop
+    const RANDOM_LOOP: [u8; 4] = [0xc0, 0xff, 0x12, 0x00];
+
+    fn random_machine(mode: RandomMode, seed: u32) -> Machine {
+        let mut machine = machine_with(&RANDOM_LOOP);
+        machine.load_fonts(&FontSet::default()).unwrap();
+        machine.set_random(mode, seed);
+        machine
+    }
+
+    fn random_bytes(machine: &mut Machine, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                machine.step_in_frame().unwrap();
+                let byte = machine.registers().read_register(0);
+                machine.step_in_frame().unwrap();
+                byte
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_cxkk_sequence() {
+        for &mode in &[RandomMode::Xorshift, RandomMode::CosmacVip] {
+            let first = random_bytes(&mut random_machine(mode, 1234), 64);
+            let second = random_bytes(&mut random_machine(mode, 1234), 64);
+            assert_eq!(first, second, "{}", mode.name());
+            assert!(first.iter().any(|&byte| byte != first[0]), "{}", mode.name());
+
+            let other_seed = random_bytes(&mut random_machine(mode, 4321), 64);
+            assert!(first != other_seed, "{}", mode.name());
+        }
+    }
+
+    #[test]
+    fn the_random_state_survives_a_save_state_round_trip() {
+        for &mode in &[RandomMode::Xorshift, RandomMode::CosmacVip] {
+            let mut machine = random_machine(mode, 99);
+            random_bytes(&mut machine, 5);
+            let saved = machine.save_state();
+            let expected = random_bytes(&mut machine, 32);
+
+            // A machine set up with another generator takes on the saved one
+            let mut restored = random_machine(RandomMode::Xorshift, 7);
+            restored.load_state(&saved).unwrap();
+            assert_eq!(random_bytes(&mut restored, 32), expected, "{}", mode.name());
+        }
+    }
+
+
+    #[test]
+    fn roms_cannot_be_loaded_over_the_fonts() {
+        let mut rom = Rom::from_bytes(&COUNTER);
+        rom.set_load_addr(0x100);
+        assert!(Machine::new().load_rom(&rom).is_err());
+    }
+
+    #[test]
+    fn load_fonts_rejects_fonts_that_overflow_the_interpreter_area() {
+        let fonts = FontSet {
+            small: vec![0xf0; 80],
+            big: vec![0xf0; 0x200],
+        };
+
+        match Machine::new().load_fonts(&fonts) {
+            Err(EmuError::InvalidFont(_)) => {}
+            other => panic!("expected an invalid font, got {:?}", other),
+        }
+    }
+}
@@ -1,60 +1,109 @@
 use std::fs::File;
 use std::fmt;
-use std::io::Read;
 use std::io::Write;
 
-const MEM_SIZE: usize = 4096;
-const ROM_ADDR: usize = 0x200;
+use super::error::EmuError;
+use super::font::{FontLayout, FontSet};
+use super::rom::{Rom, DEFAULT_LOAD_ADDR, MIN_LOAD_ADDR};
+use super::savestate::{StateReader, StateWriter};
+
+pub const MEM_SIZE: usize = 4096;
+pub const XO_CHIP_MEM_SIZE: usize = 65536;
 
 pub struct Memory {
-    pub mem: [u8; MEM_SIZE],
+    pub mem: Vec<u8>,
 }
 
 impl Memory {
-    pub fn store_program_data(&mut self, rom: File) {
-        let mut last_stored_addr = ROM_ADDR;
-
-        for byte in rom.bytes() {
-            match byte {
-                Ok(b) => {
-                    self.mem[last_stored_addr] = b;
-                    last_stored_addr += 1;
-                }
-                Err(e) => panic!("Some error {:?} occurred while storing program data.", e),
-            }
+    // Growing memory keeps its contents, so fonts and ROM data survive a switch to XO-CHIP
+    pub fn resize(&mut self, size: usize) {
+        self.mem.resize(size, 0);
+    }
+
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), EmuError> {
+        try!(rom.validate(self.mem.len()));
+
+        let load_addr = rom.load_addr() as usize;
+        for (offset, byte) in rom.data().iter().enumerate() {
+            self.mem[load_addr + offset] = *byte;
         }
+
+        Ok(())
     }
 
-    pub fn load_fonts(&mut self) {
-        let font_file = File::open("./font.bin").unwrap();
-        let mut mem_addr = 0x0;
-        for byte in font_file.bytes() {
-            match byte {
-                Ok(b) => {
-                    self.mem[mem_addr] = b;
-                    mem_addr += 1;
-                }
-                Err(e) => panic!("Some error {:?} occurred while loading font data.", e),
+    // Copies both fonts into the interpreter area and returns where they were placed
+    pub fn load_fonts(&mut self, fonts: &FontSet) -> Result<FontLayout, EmuError> {
+        let layout = fonts.layout();
+        let end = layout.big_addr as usize + fonts.big.len();
+        // Fonts stay in the interpreter area, where no program can be loaded
+        if end > MIN_LOAD_ADDR as usize {
+            return Err(EmuError::InvalidFont(format!("the fonts take {} bytes, but only {} fit \
+                                                      below {:#x}",
+                                                     end - layout.small_addr as usize,
+                                                     MIN_LOAD_ADDR - layout.small_addr,
+                                                     MIN_LOAD_ADDR)));
+        }
+
+        for (offset, byte) in fonts.small.iter().enumerate() {
+            self.mem[layout.small_addr as usize + offset] = *byte;
+        }
+        for (offset, byte) in fonts.big.iter().enumerate() {
+            self.mem[layout.big_addr as usize + offset] = *byte;
+        }
+
+        Ok(layout)
+    }
+
+    // The reserved area below the program, where the original interpreter lived
+    pub fn interpreter_page(&self) -> &[u8] {
+        &self.mem[..0x100]
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, EmuError> {
+        match self.mem.get(address as usize) {
+            Some(byte) => Ok(*byte),
+            None => Err(EmuError::MemoryOutOfBounds { addr: address }),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, new_byte: u8) -> Result<(), EmuError> {
+        match self.mem.get_mut(address as usize) {
+            Some(byte) => {
+                *byte = new_byte;
+                Ok(())
             }
+            None => Err(EmuError::MemoryOutOfBounds { addr: address }),
         }
     }
 
-    pub fn read_byte(&self, address: u16) -> u8 {
-        self.mem[address as usize]
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_block(&self.mem);
     }
 
-    pub fn write_byte(&mut self, address: u16, new_byte: u8) {
-        self.mem[address as usize] = new_byte;
+    // `size` is the size of the machine's address space, which a state can't change
+    pub fn load_state(reader: &mut StateReader, size: usize) -> Result<Memory, EmuError> {
+        let mem = try!(reader.read_block());
+        if mem.len() != size {
+            return Err(EmuError::InvalidSaveState(format!("memory is {} bytes, but this \
+                                                           machine has {}",
+                                                          mem.len(),
+                                                          size)));
+        }
+        Ok(Memory { mem: mem.to_vec() })
     }
 
-    pub fn _dump_mem_to_disk(&self) {
-        let mut out = File::create("./memdump.dmp").unwrap();
-        out.write_all(&self.mem);
-        println!("Dumped memory to disk.");
+    pub fn _dump_mem_to_disk(&self) -> Result<(), EmuError> {
+        let mut out = try!(File::create("./memdump.dmp"));
+        try!(out.write_all(&self.mem));
+        Ok(())
     }
 
     pub fn _display_pong_rom(&self) {
-        let mut addr = ROM_ADDR;
+        let mut addr = DEFAULT_LOAD_ADDR as usize;
         for _ in 1..100 {
             println!("{:#x}", self.mem[addr]);
             addr += 1;
@@ -78,6 +127,6 @@ impl fmt::Debug for Memory {
 
 impl Default for Memory {
     fn default() -> Memory {
-        Memory { mem: [0u8; MEM_SIZE] }
+        Memory { mem: vec![0u8; MEM_SIZE] }
     }
 }
@@ -1,4 +1,18 @@
 pub mod cpu;
+pub mod machine;
+pub mod instruction;
+pub mod error;
 mod register;
-mod keypad;
+pub mod keypad;
 mod memory;
+pub mod quirks;
+pub mod display;
+pub mod font;
+mod savestate;
+mod rewind;
+pub mod random;
+pub mod rom;
+pub mod breakpoint;
+mod debugger;
+mod gdb;
+pub mod trace;
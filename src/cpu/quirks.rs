@@ -0,0 +1,257 @@
+use std::fmt;
+
+use super::error::EmuError;
+use super::savestate::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx rather than shifting Vx in place
+    pub shift_uses_vy: bool,
+    // Fx55/Fx65 leave I pointing past the last register transferred
+    pub load_store_increments_i: bool,
+    // Bnnn is treated as Bxnn and jumps to xnn + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 clear VF
+    pub logic_resets_vf: bool,
+    // Dxyn clips sprites at the screen edge instead of wrapping them around
+    pub clip_sprites: bool,
+    // Dxyn ends the current frame, as the VIP waited for vertical blank before drawing
+    pub display_wait: bool,
+}
+
+pub const QUIRK_NAMES: [&'static str; 6] = ["shift_uses_vy",
+                                            "load_store_increments_i",
+                                            "jump_uses_vx",
+                                            "logic_resets_vf",
+                                            "clip_sprites",
+                                            "display_wait"];
+
+pub const PRESET_NAMES: [&'static str; 5] = ["cosmac_vip",
+                                             "chip48",
+                                             "schip_legacy",
+                                             "schip_modern",
+                                             "xo_chip"];
+
+impl Quirks {
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn schip_legacy() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    pub fn schip_modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_preset(name: &str) -> Option<Quirks> {
+        match name {
+            "cosmac_vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip_legacy" => Some(Quirks::schip_legacy()),
+            "schip_modern" => Some(Quirks::schip_modern()),
+            "xo_chip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        match name {
+            "shift_uses_vy" => self.shift_uses_vy = value,
+            "load_store_increments_i" => self.load_store_increments_i = value,
+            "jump_uses_vx" => self.jump_uses_vx = value,
+            "logic_resets_vf" => self.logic_resets_vf = value,
+            "clip_sprites" => self.clip_sprites = value,
+            "display_wait" => self.display_wait = value,
+            _ => {
+                return Err(format!("Unknown quirk '{}', expected one of: {}",
+                                   name,
+                                   QUIRK_NAMES.join(", ")))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "shift_uses_vy" => Some(self.shift_uses_vy),
+            "load_store_increments_i" => Some(self.load_store_increments_i),
+            "jump_uses_vx" => Some(self.jump_uses_vx),
+            "logic_resets_vf" => Some(self.logic_resets_vf),
+            "clip_sprites" => Some(self.clip_sprites),
+            "display_wait" => Some(self.display_wait),
+            _ => None,
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.shift_uses_vy);
+        writer.write_bool(self.load_store_increments_i);
+        writer.write_bool(self.jump_uses_vx);
+        writer.write_bool(self.logic_resets_vf);
+        writer.write_bool(self.clip_sprites);
+        writer.write_bool(self.display_wait);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Quirks, EmuError> {
+        Ok(Quirks {
+            shift_uses_vy: try!(reader.read_bool()),
+            load_store_increments_i: try!(reader.read_bool()),
+            jump_uses_vx: try!(reader.read_bool()),
+            logic_resets_vf: try!(reader.read_bool()),
+            clip_sprites: try!(reader.read_bool()),
+            display_wait: try!(reader.read_bool()),
+        })
+    }
+
+    // Accepts an override of the form `name=on`, as given on the command line
+    pub fn apply_override(&mut self, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = match parts.next() {
+            Some(v) => try!(parse_bool(v.trim())),
+            None => true,
+        };
+        self.set(name, value)
+    }
+}
+
+// No quirks at all, which is how rust8 ran programs before quirks were configurable
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "shift_uses_vy={} load_store_increments_i={} jump_uses_vx={} logic_resets_vf={} \
+                clip_sprites={} display_wait={}",
+               self.shift_uses_vy,
+               self.load_store_increments_i,
+               self.jump_uses_vx,
+               self.logic_resets_vf,
+               self.clip_sprites,
+               self.display_wait)
+    }
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "on" | "true" | "yes" => Ok(true),
+        "0" | "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("Expected on/off, got '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_name_has_a_preset() {
+        for name in PRESET_NAMES.iter() {
+            assert!(Quirks::from_preset(name).is_some(), "no preset for {}", name);
+        }
+        assert_eq!(Quirks::from_preset("chip-8"), None);
+    }
+
+    #[test]
+    fn presets_match_their_platforms() {
+        let vip = Quirks::cosmac_vip();
+        assert!(vip.shift_uses_vy && vip.load_store_increments_i && vip.logic_resets_vf);
+        assert!(vip.display_wait && !vip.jump_uses_vx);
+
+        let schip = Quirks::schip_modern();
+        assert!(!schip.shift_uses_vy && !schip.load_store_increments_i && schip.jump_uses_vx);
+        assert!(Quirks::schip_legacy().display_wait && !schip.display_wait);
+
+        let xo_chip = Quirks::xo_chip();
+        assert!(xo_chip.shift_uses_vy && xo_chip.load_store_increments_i);
+        assert!(!xo_chip.clip_sprites && !xo_chip.jump_uses_vx);
+    }
+
+    #[test]
+    fn default_has_no_quirks() {
+        let quirks = Quirks::default();
+        for name in QUIRK_NAMES.iter() {
+            assert_eq!(quirks.get(name), Some(false));
+        }
+    }
+
+    #[test]
+    fn apply_override_sets_one_quirk() {
+        let mut quirks = Quirks::default();
+
+        quirks.apply_override("clip_sprites=on").unwrap();
+        assert!(quirks.clip_sprites);
+        quirks.apply_override(" clip_sprites = off ").unwrap();
+        assert!(!quirks.clip_sprites);
+        // A bare name turns the quirk on
+        quirks.apply_override("display_wait").unwrap();
+        assert!(quirks.display_wait);
+
+        assert_eq!(Quirks { display_wait: false, ..quirks }, Quirks::default());
+    }
+
+    #[test]
+    fn apply_override_rejects_bad_settings() {
+        let mut quirks = Quirks::default();
+
+        assert!(quirks.apply_override("clip_sprite=on").is_err());
+        assert!(quirks.apply_override("clip_sprites=maybe").is_err());
+        assert_eq!(quirks, Quirks::default());
+    }
+}
@@ -0,0 +1,152 @@
+use super::error::EmuError;
+use super::savestate::{StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomMode {
+    Xorshift,
+    CosmacVip,
+}
+
+impl RandomMode {
+    pub fn from_name(name: &str) -> Option<RandomMode> {
+        match name {
+            "xorshift" => Some(RandomMode::Xorshift),
+            "cosmac_vip" => Some(RandomMode::CosmacVip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RandomMode::Xorshift => "xorshift",
+            RandomMode::CosmacVip => "cosmac_vip",
+        }
+    }
+
+    fn to_u8(&self) -> u8 {
+        match *self {
+            RandomMode::Xorshift => 0,
+            RandomMode::CosmacVip => 1,
+        }
+    }
+}
+
+impl Default for RandomMode {
+    fn default() -> RandomMode {
+        RandomMode::Xorshift
+    }
+}
+
+// Random source for Cxkk. Everything it depends on is part of the machine state, so a
+// ROM run twice from the same seed and with the same input behaves the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Random {
+    mode: RandomMode,
+    state: u32,
+}
+
+impl Random {
+    pub fn new(mode: RandomMode, seed: u32) -> Random {
+        let state = match mode {
+            // Small seeds are spread across all 32 bits so the first values aren't
+            // near zero. Xorshift never leaves zero, so that state is avoided.
+            RandomMode::Xorshift => {
+                match (seed ^ 0x2545_f491).wrapping_mul(0x9e37_79b9) {
+                    0 => 0x2545_f491,
+                    state => state,
+                }
+            }
+            // The seed is the initial value of the VIP's 16-bit R9 register
+            RandomMode::CosmacVip => seed & 0xffff,
+        };
+
+        Random {
+            mode: mode,
+            state: state,
+        }
+    }
+
+    // The VIP interpreter kept its seed in R9. The interrupt routine stepped the low
+    // byte every frame, and Cxkk stepped it again, added the byte it pointed at in the
+    // interpreter's own page to the high byte and returned that sum.
+    //
+    // `interpreter_page` stands in for the VIP's page: it is the first 256 bytes of
+    // memory, where our fonts live rather than 1802 code, so the routine is the same
+    // but the numbers it produces differ from a real VIP.
+    pub fn next_byte(&mut self, interpreter_page: &[u8]) -> u8 {
+        match self.mode {
+            RandomMode::Xorshift => {
+                self.state ^= self.state << 13;
+                self.state ^= self.state >> 17;
+                self.state ^= self.state << 5;
+                (self.state >> 24) as u8
+            }
+            RandomMode::CosmacVip => {
+                let low = (self.state as u8).wrapping_add(1);
+                let high = ((self.state >> 8) as u8).wrapping_add(interpreter_page[low as usize]);
+                self.state = (high as u32) << 8 | low as u32;
+                high
+            }
+        }
+    }
+
+    // Called once per frame, like the VIP's interrupt routine
+    pub fn tick(&mut self) {
+        if self.mode == RandomMode::CosmacVip {
+            let low = (self.state as u8).wrapping_add(1);
+            self.state = self.state & 0xff00 | low as u32;
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.mode.to_u8());
+        writer.write_u32(self.state);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Random, EmuError> {
+        let mode = match try!(reader.read_u8()) {
+            0 => RandomMode::Xorshift,
+            1 => RandomMode::CosmacVip,
+            other => {
+                return Err(EmuError::InvalidSaveState(format!("unknown random generator {}", other)))
+            }
+        };
+
+        Ok(Random {
+            mode: mode,
+            state: try!(reader.read_u32()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosmac_vip_adds_the_byte_under_the_low_seed_to_the_high_seed() {
+        let mut page = [0u8; 256];
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        page[0x00] = 0x80;
+
+        let mut rng = Random::new(RandomMode::CosmacVip, 0x05fe);
+        // 0x05 + page[0xff], wrapping
+        assert_eq!(rng.next_byte(&page), 0x04);
+        // 0x04 + page[0x00]
+        assert_eq!(rng.next_byte(&page), 0x84);
+        // Each frame steps the low byte without touching the sum
+        rng.tick();
+        rng.tick();
+        assert_eq!(rng.next_byte(&page), 0x87);
+    }
+
+    #[test]
+    fn modes_are_named() {
+        for &mode in &[RandomMode::Xorshift, RandomMode::CosmacVip] {
+            assert_eq!(RandomMode::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(RandomMode::from_name("vip"), None);
+    }
+}
@@ -1,7 +1,10 @@
-use super::cpu::JumpType;
+use super::machine::JumpType;
+use super::error::EmuError;
+use super::savestate::{StateReader, StateWriter};
 use std::fmt;
 
 const ROM_ADDR: usize = 0x200;
+const STACK_SIZE: usize = 16;
 
 #[derive(Default)]
 pub struct Registers {
@@ -14,7 +17,7 @@ pub struct Registers {
     reg_pc: u16,
     reg_sp: u8,
 
-    stack: [u16; 16],
+    stack: [u16; STACK_SIZE],
 }
 
 impl fmt::Debug for Registers {
@@ -85,8 +88,17 @@ impl Registers {
         self.reg_pc
     }
 
+    pub fn write_pc(&mut self, addr: u16) {
+        self.reg_pc = addr;
+    }
+
+    // Return addresses, innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.reg_sp as usize]
+    }
+
     pub fn increment_pc(&mut self) {
-        self.reg_pc += 2;
+        self.reg_pc = self.reg_pc.wrapping_add(2);
     }
 
     pub fn set_vf(&mut self) {
@@ -97,19 +109,82 @@ impl Registers {
         self.reg_gp[15] = 0;
     }
 
-    pub fn jump_to_address(&mut self, addr: u16, jump_type: JumpType) {
+    pub fn jump_to_address(&mut self, addr: u16, jump_type: JumpType) -> Result<(), EmuError> {
         match jump_type {
             JumpType::SUBROUTINE => {
+                if self.reg_sp as usize >= STACK_SIZE {
+                    return Err(EmuError::StackOverflow { pc: self.reg_pc.wrapping_sub(2) });
+                }
                 self.stack[self.reg_sp as usize] = self.reg_pc;
                 self.reg_sp += 1;
             }
             JumpType::NORMAL => {}
         }
         self.reg_pc = addr;
+        Ok(())
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.reg_gp);
+        writer.write_u16(self.reg_i);
+        writer.write_u8(self.reg_delay);
+        writer.write_u8(self.reg_sound);
+        writer.write_u16(self.reg_pc);
+        writer.write_u8(self.reg_sp);
+        for addr in self.stack.iter() {
+            writer.write_u16(*addr);
+        }
     }
 
-    pub fn return_from_subroutine(&mut self) {
+    pub fn load_state(reader: &mut StateReader) -> Result<Registers, EmuError> {
+        let mut reg = Registers::default();
+        reg.reg_gp.copy_from_slice(try!(reader.read_bytes(16)));
+        reg.reg_i = try!(reader.read_u16());
+        reg.reg_delay = try!(reader.read_u8());
+        reg.reg_sound = try!(reader.read_u8());
+        reg.reg_pc = try!(reader.read_u16());
+        reg.reg_sp = try!(reader.read_u8());
+        for addr in reg.stack.iter_mut() {
+            *addr = try!(reader.read_u16());
+        }
+
+        if reg.reg_sp as usize > STACK_SIZE {
+            return Err(EmuError::InvalidSaveState(format!("stack pointer {} out of range", reg.reg_sp)));
+        }
+
+        Ok(reg)
+    }
+
+    pub fn return_from_subroutine(&mut self) -> Result<(), EmuError> {
+        if self.reg_sp == 0 {
+            return Err(EmuError::StackUnderflow { pc: self.reg_pc.wrapping_sub(2) });
+        }
         self.reg_pc = self.stack[(self.reg_sp - 1) as usize];
         self.reg_sp -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_errors_report_the_previous_instruction_even_after_pc_wraps() {
+        let mut reg = Registers::new();
+        reg.write_pc(0);
+        match reg.return_from_subroutine() {
+            Err(EmuError::StackUnderflow { pc: 0xfffe }) => {}
+            other => panic!("expected a stack underflow, got {:?}", other),
+        }
+
+        for _ in 0..STACK_SIZE {
+            reg.jump_to_address(0, JumpType::SUBROUTINE).unwrap();
+        }
+        reg.write_pc(1);
+        match reg.jump_to_address(0, JumpType::SUBROUTINE) {
+            Err(EmuError::StackOverflow { pc: 0xffff }) => {}
+            other => panic!("expected a stack overflow, got {:?}", other),
+        }
     }
 }
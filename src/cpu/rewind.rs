@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+
+// History of machine snapshots, one per frame. Only the newest snapshot is kept in
+// full; every older frame is stored as the difference from the frame after it, so
+// walking backwards is a matter of undoing one delta at a time.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Snapshot>,
+}
+
+enum Snapshot {
+    // XOR against the next newer frame, run-length encoded
+    Delta(Vec<u8>),
+    // Used when the snapshot size changed between frames and a delta is impossible
+    Full(Vec<u8>),
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(previous) = self.latest.take() {
+            let snapshot = if previous.len() == state.len() {
+                Snapshot::Delta(encode_delta(&previous, &state))
+            } else {
+                Snapshot::Full(previous)
+            };
+
+            self.history.push_back(snapshot);
+            while self.history.len() >= self.capacity {
+                self.history.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+    }
+
+    // Steps back one frame, returning the snapshot to restore
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let snapshot = match self.history.pop_back() {
+            Some(s) => s,
+            None => return self.latest.as_ref().map(|s| s.as_slice()),
+        };
+
+        let previous = match snapshot {
+            Snapshot::Delta(delta) => {
+                let mut state = self.latest.take().unwrap_or(Vec::new());
+                apply_delta(&mut state, &delta);
+                state
+            }
+            Snapshot::Full(state) => state,
+        };
+
+        self.latest = Some(previous);
+        self.latest.as_ref().map(|s| s.as_slice())
+    }
+}
+
+// The delta is a sequence of (unchanged run, changed run, changed bytes) records,
+// where the changed bytes are the XOR of both frames. Runs are stored as varints.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut pos = 0;
+
+    while pos < from.len() {
+        let unchanged_start = pos;
+        while pos < from.len() && from[pos] == to[pos] {
+            pos += 1;
+        }
+        let changed_start = pos;
+        while pos < from.len() && from[pos] != to[pos] {
+            pos += 1;
+        }
+
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, pos - changed_start);
+        for i in changed_start..pos {
+            delta.push(from[i] ^ to[i]);
+        }
+    }
+
+    delta
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut cursor = 0;
+
+    while cursor < delta.len() {
+        pos += read_varint(delta, &mut cursor);
+        let changed = read_varint(delta, &mut cursor);
+        for _ in 0..changed {
+            state[pos] ^= delta[cursor];
+            pos += 1;
+            cursor += 1;
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*cursor];
+        *cursor += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize, fill: u8) -> Vec<u8> {
+        vec![fill; len]
+    }
+
+    #[test]
+    fn delta_round_trips_runs_longer_than_a_varint_byte() {
+        let from = frame(1000, 0);
+        let mut to = from.clone();
+        // A 300 byte unchanged run, 200 changed bytes, then a single change at the end
+        for i in 300..500 {
+            to[i] = i as u8 | 1;
+        }
+        to[999] = 0xff;
+
+        let delta = encode_delta(&from, &to);
+        let mut state = to.clone();
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, from);
+
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, to);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for &value in &[0, 1, 127, 128, 300, 16383, 16384, 1 << 20] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut cursor = 0;
+            assert_eq!(read_varint(&out, &mut cursor), value);
+            assert_eq!(cursor, out.len());
+        }
+    }
+
+    #[test]
+    fn pop_walks_back_one_frame_at_a_time() {
+        let mut rewind = RewindBuffer::new(10);
+        for fill in 0..4 {
+            rewind.push(frame(64, fill));
+        }
+
+        assert_eq!(rewind.pop(), Some(&frame(64, 2)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(64, 1)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(64, 0)[..]));
+    }
+
+    #[test]
+    fn size_changes_fall_back_to_full_snapshots() {
+        // Lo-res to hi-res display, then 4K to 64K memory
+        let mut rewind = RewindBuffer::new(10);
+        rewind.push(frame(4096 + 256, 1));
+        rewind.push(frame(4096 + 1024, 2));
+        rewind.push(frame(65536 + 1024, 3));
+        rewind.push(frame(65536 + 1024, 4));
+
+        assert_eq!(rewind.pop(), Some(&frame(65536 + 1024, 3)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(4096 + 1024, 2)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(4096 + 256, 1)[..]));
+    }
+
+    #[test]
+    fn eviction_keeps_the_oldest_remaining_frame_decodable() {
+        let mut rewind = RewindBuffer::new(3);
+        for fill in 0..6 {
+            rewind.push(frame(64, fill));
+        }
+
+        // Three frames fit: the latest (5) and two steps back
+        assert_eq!(rewind.pop(), Some(&frame(64, 4)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(64, 3)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(64, 3)[..]));
+    }
+
+    #[test]
+    fn rewinding_past_the_start_stays_on_the_oldest_frame() {
+        let mut rewind = RewindBuffer::new(10);
+        rewind.push(frame(16, 7));
+        rewind.push(frame(16, 8));
+
+        assert_eq!(rewind.pop(), Some(&frame(16, 7)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(16, 7)[..]));
+        assert_eq!(rewind.pop(), Some(&frame(16, 7)[..]));
+    }
+
+    #[test]
+    fn empty_or_disabled_buffers_have_nothing_to_rewind() {
+        assert_eq!(RewindBuffer::new(10).pop(), None);
+
+        let mut disabled = RewindBuffer::new(0);
+        disabled.push(frame(16, 1));
+        assert_eq!(disabled.pop(), None);
+    }
+}
@@ -0,0 +1,283 @@
+use super::error::EmuError;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Where CHIP-8 programs are normally loaded and started
+pub const DEFAULT_LOAD_ADDR: u16 = 0x200;
+// Everything below this is the interpreter area, where the fonts are loaded
+pub const MIN_LOAD_ADDR: u16 = 0x200;
+
+// A program image and the address it's loaded and started at
+#[derive(Clone)]
+pub struct Rom {
+    data: Vec<u8>,
+    load_addr: u16,
+}
+
+impl Rom {
+    pub fn from_bytes(data: &[u8]) -> Rom {
+        Rom {
+            data: data.to_vec(),
+            load_addr: DEFAULT_LOAD_ADDR,
+        }
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Rom, EmuError> {
+        let mut data = Vec::new();
+        try!(reader.read_to_end(&mut data));
+        Ok(Rom::from_bytes(&data))
+    }
+
+    pub fn from_path(path: &Path) -> Result<Rom, EmuError> {
+        Rom::from_reader(try!(File::open(path)))
+    }
+
+    // ETI-660 programs, for example, start at 0x600
+    pub fn set_load_addr(&mut self, load_addr: u16) {
+        self.load_addr = load_addr;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn load_addr(&self) -> u16 {
+        self.load_addr
+    }
+
+    // Checks that the program fits in `mem_size` bytes of memory at its load address
+    pub fn validate(&self, mem_size: usize) -> Result<(), EmuError> {
+        if self.data.is_empty() {
+            return Err(EmuError::InvalidRom("the file is empty".to_string()));
+        }
+
+        let load_addr = self.load_addr as usize;
+        if self.load_addr < MIN_LOAD_ADDR {
+            return Err(EmuError::InvalidRom(format!("load address {:#x} is inside the \
+                                                     interpreter area below {:#x}",
+                                                    load_addr,
+                                                    MIN_LOAD_ADDR)));
+        }
+        if load_addr >= mem_size {
+            return Err(EmuError::InvalidRom(format!("load address {:#x} is outside {} bytes \
+                                                     of memory",
+                                                    load_addr,
+                                                    mem_size)));
+        }
+        if self.data.len() > mem_size - load_addr {
+            return Err(EmuError::RomTooLarge {
+                size: self.data.len(),
+                max: mem_size - load_addr,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn crc32(&self) -> u32 {
+        crc32(&self.data)
+    }
+
+    pub fn sha1(&self) -> Sha1Digest {
+        Sha1Digest(sha1(&self.data))
+    }
+}
+
+impl fmt::Display for Rom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} bytes at {:#05x}, SHA-1 {}, CRC-32 {:08x}",
+               self.len(),
+               self.load_addr,
+               self.sha1(),
+               self.crc32())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Sha1Digest(pub [u8; 20]);
+
+impl fmt::Display for Sha1Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            try!(write!(f, "{:02x}", byte));
+        }
+        Ok(())
+    }
+}
+
+// CRC-32 (IEEE), which movies use to check they're played back against the ROM and
+// fonts they were recorded with
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// SHA-1 as in FIPS 180-4. It identifies ROMs, which are small enough that hashing them
+// in one go is fine.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+
+    let mut message = data.to_vec();
+    let bit_len = (data.len() as u64).wrapping_mul(8);
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    for shift in (0..8).rev() {
+        message.push((bit_len >> (shift * 8)) as u8);
+    }
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = (word[0] as u32) << 24 | (word[1] as u32) << 16 | (word[2] as u32) << 8 |
+                   word[3] as u32;
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a.rotate_left(5)
+                        .wrapping_add(f)
+                        .wrapping_add(e)
+                        .wrapping_add(k)
+                        .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, value) in h.iter().enumerate() {
+        for j in 0..4 {
+            digest[i * 4 + j] = (value >> (24 - j * 8)) as u8;
+        }
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+
+    fn rom_at(len: usize, load_addr: u16) -> Rom {
+        let mut rom = Rom::from_bytes(&vec![0xaa; len]);
+        rom.set_load_addr(load_addr);
+        rom
+    }
+
+    #[test]
+    fn validate_rejects_empty_roms() {
+        match Rom::from_bytes(&[]).validate(4096) {
+            Err(EmuError::InvalidRom(_)) => {}
+            other => panic!("expected an invalid ROM, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_rejects_roms_that_overrun_memory() {
+        assert!(rom_at(4096 - 0x200, 0x200).validate(4096).is_ok());
+        match rom_at(4096 - 0x200 + 1, 0x200).validate(4096) {
+            Err(EmuError::RomTooLarge { size, max }) => assert_eq!((size, max), (3585, 3584)),
+            other => panic!("expected a ROM that is too large, got {:?}", other),
+        }
+
+        assert!(rom_at(0x100, 0x600).validate(4096).is_ok());
+        assert!(rom_at(4096 - 0x600 + 1, 0x600).validate(4096).is_err());
+        assert!(rom_at(4096, 0x200).validate(65536).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_load_addresses_outside_memory() {
+        assert!(rom_at(2, 0xfff).validate(4096).is_err());
+        assert!(rom_at(1, 0xfff).validate(4096).is_ok());
+        assert!(rom_at(1, 0x1000).validate(4096).is_err());
+        assert!(rom_at(1, 0x1000).validate(65536).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_load_addresses_over_the_fonts() {
+        for &load_addr in &[0x000, 0x050, 0x1ff] {
+            match rom_at(2, load_addr).validate(4096) {
+                Err(EmuError::InvalidRom(_)) => {}
+                other => panic!("expected {:#x} to be rejected, got {:?}", load_addr, other),
+            }
+        }
+    }
+
+    #[test]
+    fn from_reader_reads_the_whole_program() {
+        let rom = Rom::from_reader(&[0x00, 0xe0, 0x12, 0x00][..]).unwrap();
+        assert_eq!(rom.data(), &[0x00, 0xe0, 0x12, 0x00]);
+        assert_eq!(rom.load_addr(), DEFAULT_LOAD_ADDR);
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "unreadable"))
+        }
+    }
+
+    #[test]
+    fn from_reader_passes_on_read_errors() {
+        match Rom::from_reader(FailingReader) {
+            Err(EmuError::Io(_)) => {}
+            other => panic!("expected an I/O error, got {:?}", other.map(|rom| rom.len())),
+        }
+    }
+
+    #[test]
+    fn sha1_matches_the_fips_test_vectors() {
+        let digest = |data: &[u8]| Sha1Digest(sha1(data)).to_string();
+        assert_eq!(digest(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(digest(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        // Long enough to need a second block
+        assert_eq!(digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
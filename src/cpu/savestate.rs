@@ -0,0 +1,123 @@
+use super::error::EmuError;
+
+pub const SAVE_STATE_MAGIC: &'static [u8; 4] = b"R8SS";
+pub const SAVE_STATE_VERSION: u16 = 5;
+
+// Save states are a flat little-endian byte stream. Each component writes its own
+// fields in a fixed order and reads them back in the same order.
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { buf: Vec::new() };
+        writer.write_bytes(SAVE_STATE_MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.push(value as u8);
+        self.buf.push((value >> 8) as u8);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_u16(value as u16);
+        self.write_u16((value >> 16) as u16);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // Variable-length data is prefixed with its length
+    pub fn write_block(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.write_bytes(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, EmuError> {
+        let mut reader = StateReader {
+            data: data,
+            pos: 0,
+        };
+
+        if try!(reader.read_bytes(SAVE_STATE_MAGIC.len())) != SAVE_STATE_MAGIC {
+            return Err(EmuError::InvalidSaveState("not a save state file".to_string()));
+        }
+
+        let version = try!(reader.read_u16());
+        if version != SAVE_STATE_VERSION {
+            return Err(EmuError::InvalidSaveState(format!("unsupported version {}, expected {}",
+                                                          version,
+                                                          SAVE_STATE_VERSION)));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EmuError> {
+        let bytes = try!(self.read_bytes(1));
+        Ok(bytes[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, EmuError> {
+        match try!(self.read_u8()) {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(EmuError::InvalidSaveState(format!("invalid boolean {}", other))),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, EmuError> {
+        let bytes = try!(self.read_bytes(2));
+        Ok((bytes[0] as u16) | ((bytes[1] as u16) << 8))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, EmuError> {
+        let low = try!(self.read_u16()) as u32;
+        let high = try!(self.read_u16()) as u32;
+        Ok(low | (high << 16))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], EmuError> {
+        if self.data.len() - self.pos < len {
+            return Err(EmuError::InvalidSaveState("unexpected end of file".to_string()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_block(&mut self) -> Result<&'a [u8], EmuError> {
+        let len = try!(self.read_u32()) as usize;
+        self.read_bytes(len)
+    }
+
+    pub fn finish(&self) -> Result<(), EmuError> {
+        if self.pos != self.data.len() {
+            return Err(EmuError::InvalidSaveState("trailing data after machine state".to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use super::register::Registers;
+use super::breakpoint::OpcodePattern;
+use super::instruction::Instruction;
+use super::error::EmuError;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// One tab-separated line per instruction, so traces can be cut, grepped and diffed
+const HEADER: &'static str = "frame\tpc\topcode\tmnemonic\tv_before\ti_before\tdt_before\t\
+                              st_before\tsp_before\tv_after\ti_after\tdt_after\tst_after\tsp_after";
+
+// Which instructions get traced. An empty list of either kind doesn't filter.
+#[derive(Clone, Default)]
+pub struct TraceFilter {
+    // Inclusive start and end addresses
+    pub pc_ranges: Vec<(u16, u16)>,
+    pub opcodes: Vec<OpcodePattern>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, word: u16) -> bool {
+        let pc_matches = self.pc_ranges.is_empty() ||
+                         self.pc_ranges.iter().any(|&(start, end)| pc >= start && pc <= end);
+        let opcode_matches = self.opcodes.is_empty() ||
+                             self.opcodes.iter().any(|pattern| pattern.matches(word));
+        pc_matches && opcode_matches
+    }
+}
+
+// The parts of the register file a trace line shows
+#[derive(Clone, Copy)]
+pub struct RegisterSnapshot {
+    v: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    sp: usize,
+}
+
+impl RegisterSnapshot {
+    pub fn of(reg: &Registers) -> RegisterSnapshot {
+        let mut v = [0; 16];
+        for (x, value) in v.iter_mut().enumerate() {
+            *value = reg.read_register(x as u8);
+        }
+
+        RegisterSnapshot {
+            v: v,
+            i: reg.read_register_i(),
+            dt: reg.read_delay_timer(),
+            st: reg.read_sound_timer(),
+            sp: reg.stack().len(),
+        }
+    }
+
+    fn columns(&self) -> String {
+        let v: String = self.v.iter().map(|value| format!("{:02x}", value)).collect();
+        format!("{}\t{:04x}\t{:02x}\t{:02x}\t{}", v, self.i, self.dt, self.st, self.sp)
+    }
+}
+
+pub struct Tracer {
+    out: BufWriter<File>,
+    filter: TraceFilter,
+    // Frames completed since tracing began
+    frame: u64,
+}
+
+impl Tracer {
+    pub fn create(path: &Path, filter: TraceFilter) -> Result<Tracer, EmuError> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        try!(writeln!(out, "{}", HEADER));
+
+        Ok(Tracer {
+            out: out,
+            filter: filter,
+            frame: 0,
+        })
+    }
+
+    pub fn wants(&self, pc: u16, word: u16) -> bool {
+        self.filter.matches(pc, word)
+    }
+
+    pub fn record(&mut self,
+                  pc: u16,
+                  word: u16,
+                  instruction: &Instruction,
+                  before: &RegisterSnapshot,
+                  after: &RegisterSnapshot)
+                  -> Result<(), EmuError> {
+        try!(writeln!(self.out,
+                      "{}\t{:04x}\t{:04x}\t{}\t{}\t{}",
+                      self.frame,
+                      pc,
+                      word,
+                      instruction,
+                      before.columns(),
+                      after.columns()));
+        Ok(())
+    }
+
+    // Flushing once a frame keeps the file current if emulation stops with an error
+    pub fn end_frame(&mut self) -> Result<(), EmuError> {
+        self.frame += 1;
+        try!(self.out.flush());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::machine::Machine;
+    use cpu::rom::Rom;
+
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+
+    // ld V0 5; ld I 0x300; call 0x208; jp 0x206; add V0 0xfe; ret
+    const PROGRAM: [u8; 12] = [0x60, 0x05, 0xa3, 0x00, 0x22, 0x08, 0x12, 0x06, 0x70, 0xfe, 0x00,
+                               0xee];
+
+    // Runs PROGRAM for two frames of three instructions and returns the trace's lines
+    fn trace(name: &str, filter: TraceFilter) -> Vec<String> {
+        let path = env::temp_dir().join(name);
+        {
+            let mut machine = Machine::new();
+            machine.load_rom(&Rom::from_bytes(&PROGRAM)).unwrap();
+            machine.set_instructions_per_frame(3);
+            machine.set_tracer(Tracer::create(&path, filter).unwrap());
+            machine.run_frame().unwrap();
+            machine.run_frame().unwrap();
+        }
+
+        let mut text = String::new();
+        File::open(&path).unwrap().read_to_string(&mut text).unwrap();
+        fs::remove_file(&path).unwrap();
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn trace_lines_are_tab_separated_columns() {
+        let lines = trace("rust8-trace-golden.tsv", TraceFilter::default());
+        let zeros = "00".repeat(15);
+        let v = |v0: &str| format!("{}{}", v0, zeros);
+        let regs = |v0: &str, i: &str, sp: usize| format!("{}\t{}\t00\t00\t{}", v(v0), i, sp);
+
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(lines[1..].to_vec(),
+                   vec![format!("0\t0200\t6005\tld V0 0x5\t{}\t{}",
+                                regs("00", "0000", 0),
+                                regs("05", "0000", 0)),
+                        format!("0\t0202\ta300\tld I 0x300\t{}\t{}",
+                                regs("05", "0000", 0),
+                                regs("05", "0300", 0)),
+                        format!("0\t0204\t2208\tcall 0x208\t{}\t{}",
+                                regs("05", "0300", 0),
+                                regs("05", "0300", 1)),
+                        format!("1\t0208\t70fe\tadd V0 0xfe\t{}\t{}",
+                                regs("05", "0300", 1),
+                                regs("03", "0300", 1)),
+                        format!("1\t020a\t00ee\tret\t{}\t{}",
+                                regs("03", "0300", 1),
+                                regs("03", "0300", 0)),
+                        format!("1\t0206\t1206\tjmp 0x206\t{}\t{}",
+                                regs("03", "0300", 0),
+                                regs("03", "0300", 0))]);
+    }
+
+
+    #[test]
+    fn filters_need_a_matching_pc_and_opcode() {
+        let mut filter = TraceFilter::default();
+        assert!(filter.matches(0x200, 0x6005));
+
+        filter.pc_ranges.push((0x206, 0x20a));
+        filter.pc_ranges.push((0x300, 0x300));
+        assert!(filter.matches(0x206, 0x6005));
+        assert!(filter.matches(0x20a, 0x6005));
+        assert!(filter.matches(0x300, 0x6005));
+        assert!(!filter.matches(0x204, 0x6005));
+        assert!(!filter.matches(0x20c, 0x6005));
+
+        filter.opcodes.push(OpcodePattern::parse("7xkk").unwrap());
+        filter.opcodes.push(OpcodePattern::parse("00EE").unwrap());
+        assert!(filter.matches(0x208, 0x70fe));
+        assert!(filter.matches(0x20a, 0x00ee));
+        assert!(!filter.matches(0x206, 0x1206));
+        assert!(!filter.matches(0x200, 0x7001));
+    }
+
+    #[test]
+    fn only_filtered_instructions_are_traced() {
+        let filter = TraceFilter {
+            pc_ranges: vec![(0x206, 0x20a)],
+            opcodes: vec![OpcodePattern::parse("7xkk").unwrap(),
+                          OpcodePattern::parse("00EE").unwrap()],
+        };
+        let lines = trace("rust8-trace-filtered.tsv", filter);
+
+        let traced: Vec<Vec<&str>> = lines[1..]
+                                         .iter()
+                                         .map(|line| line.split('\t').take(4).collect())
+                                         .collect();
+        assert_eq!(traced,
+                   vec![vec!["1", "0208", "70fe", "add V0 0xfe"],
+                        vec!["1", "020a", "00ee", "ret"]]);
+    }
+}
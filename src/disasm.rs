@@ -0,0 +1,397 @@
+use cpu::instruction::{decode_with_next, Instruction};
+use cpu::rom::Rom;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const USAGE: &'static str = "Usage: chip8 disasm <rom> [options]
+
+Options:
+    --syntax <octo|classic>   Octo source that can be reassembled, or a classic
+                              listing with addresses and opcodes (default octo)
+    --xo                      Decode XO-CHIP instructions";
+
+const PROGRAM_START: u16 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Octo,
+    Classic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Main,
+    Subroutine,
+    Jump,
+    Data,
+}
+
+// Code is found by following control flow from the entry point, so bytes are only
+// shown as instructions when some path actually executes them.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    xo_chip: bool,
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom: &'a [u8], xo_chip: bool) -> Disassembler<'a> {
+        let mut disassembler = Disassembler {
+            rom: rom,
+            xo_chip: xo_chip,
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembler.trace();
+        disassembler
+    }
+
+    pub fn render(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+        // A full XO-CHIP ROM ends at 0x10000, which doesn't fit in a u16
+        let end = PROGRAM_START as u32 + self.rom.len() as u32;
+        let mut position = PROGRAM_START as u32;
+
+        while position < end {
+            let addr = position as u16;
+            if let Some(kind) = self.labels.get(&addr) {
+                let name = self.label_name(addr, *kind);
+                match syntax {
+                    Syntax::Octo => out.push_str(&format!(": {}\n", name)),
+                    Syntax::Classic => out.push_str(&format!("{}:\n", name)),
+                }
+            }
+
+            let instruction = match self.instructions.get(&addr) {
+                // An instruction that overlaps another one or a label is shown as data,
+                // so that every label gets a line of its own
+                Some(instruction) if !self.overlaps(addr, instruction) => Some(*instruction),
+                _ => None,
+            };
+
+            match instruction {
+                Some(instruction) => {
+                    out.push_str(&self.render_instruction(addr, &instruction, syntax));
+                    position += instruction_size(&instruction) as u32;
+                }
+                None => {
+                    let data_end = self.data_end(addr, end);
+                    let referenced = self.labels.get(&addr) == Some(&LabelKind::Data);
+                    out.push_str(&self.render_data(addr, data_end, referenced, syntax));
+                    position = data_end;
+                }
+            }
+        }
+
+        out
+    }
+
+    fn trace(&mut self) {
+        let mut pending = vec![PROGRAM_START];
+        self.labels.insert(PROGRAM_START, LabelKind::Main);
+
+        while let Some(addr) = pending.pop() {
+            if self.instructions.contains_key(&addr) {
+                continue;
+            }
+
+            let instruction = match self.decode_at(addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            self.instructions.insert(addr, instruction);
+
+            let next = addr.wrapping_add(instruction_size(&instruction));
+            match instruction {
+                Instruction::Jp(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                Instruction::JpV0(target) => {
+                    // Usually a jump table; its first entry is the best guess we have
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push(target);
+                }
+                Instruction::Call(target) => {
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                Instruction::Ret | Instruction::Exit => {}
+                Instruction::SeVxByte { .. } |
+                Instruction::SneVxByte { .. } |
+                Instruction::SeVxVy { .. } |
+                Instruction::SneVxVy { .. } |
+                Instruction::SkpVx { .. } |
+                Instruction::SknpVx { .. } => {
+                    pending.push(next);
+                    let skipped = match self.decode_at(next) {
+                        Some(skipped) => instruction_size(&skipped),
+                        None => 2,
+                    };
+                    pending.push(next.wrapping_add(skipped));
+                }
+                Instruction::LdIAddr(target) => {
+                    self.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                }
+                Instruction::LdILong(target) => {
+                    self.add_label(target, LabelKind::Data);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        if !self.in_rom(addr) {
+            return;
+        }
+        // A subroutine that is also jumped to keeps its subroutine name
+        let entry = self.labels.entry(addr).or_insert(kind);
+        if kind < *entry {
+            *entry = kind;
+        }
+    }
+
+    fn in_rom(&self, addr: u16) -> bool {
+        addr >= PROGRAM_START && ((addr - PROGRAM_START) as usize) < self.rom.len()
+    }
+
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        if !self.in_rom(addr) || !self.in_rom(addr.wrapping_add(1)) {
+            return None;
+        }
+        let offset = (addr - PROGRAM_START) as usize;
+        Some(((self.rom[offset] as u16) << 8) | self.rom[offset + 1] as u16)
+    }
+
+    fn decode_at(&self, addr: u16) -> Option<Instruction> {
+        let word = match self.word_at(addr) {
+            Some(word) => word,
+            None => return None,
+        };
+        let next = self.word_at(addr.wrapping_add(2));
+        match decode_with_next(word, next.unwrap_or(0)) {
+            Ok(instruction) => {
+                if instruction.is_xo_chip() && !self.xo_chip {
+                    return None;
+                }
+                // A long load cut off by the end of the ROM is data
+                if let Instruction::LdILong(_) = instruction {
+                    if next.is_none() {
+                        return None;
+                    }
+                }
+                Some(instruction)
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn overlaps(&self, addr: u16, instruction: &Instruction) -> bool {
+        (1..instruction_size(instruction)).any(|offset| {
+            let inner = addr.wrapping_add(offset);
+            self.instructions.contains_key(&inner) || self.labels.contains_key(&inner)
+        })
+    }
+
+    // Data runs until the next instruction or label
+    fn data_end(&self, start: u16, end: u32) -> u32 {
+        let mut position = start as u32 + 1;
+        while position < end && !self.instructions.contains_key(&(position as u16)) &&
+              !self.labels.contains_key(&(position as u16)) {
+            position += 1;
+        }
+        position
+    }
+
+    fn label_name(&self, addr: u16, kind: LabelKind) -> String {
+        match kind {
+            LabelKind::Main => "main".to_string(),
+            LabelKind::Subroutine => format!("sub_{:03x}", addr),
+            LabelKind::Jump => format!("label_{:03x}", addr),
+            LabelKind::Data => format!("data_{:03x}", addr),
+        }
+    }
+
+    // Addresses outside the ROM have no label and are written as numbers
+    fn target(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(kind) => self.label_name(addr, *kind),
+            None => format!("{:#05x}", addr),
+        }
+    }
+
+    fn render_instruction(&self, addr: u16, instruction: &Instruction, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Octo => format!("\t{}\n", self.octo_mnemonic(instruction)),
+            Syntax::Classic => {
+                let mut opcode = format!("{:04x}", self.word_at(addr).unwrap_or(0));
+                if let Instruction::LdILong(target) = *instruction {
+                    opcode.push_str(&format!(" {:04x}", target));
+                }
+                format!("{:#05x}  {:<9}  {}\n",
+                        addr,
+                        opcode,
+                        self.classic_mnemonic(instruction))
+            }
+        }
+    }
+
+    fn classic_mnemonic(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::Jp(target) => format!("jmp {}", self.target(target)),
+            Instruction::Call(target) => format!("call {}", self.target(target)),
+            Instruction::LdIAddr(target) => format!("ld I {}", self.target(target)),
+            Instruction::JpV0(target) => format!("jp V0 {}", self.target(target)),
+            Instruction::LdILong(target) => format!("ld I long {}", self.target(target)),
+            _ => format!("{}", instruction),
+        }
+    }
+
+    fn octo_mnemonic(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::Sys(target) => {
+                format!("{:#04x} {:#04x} # sys {:#05x}", target >> 8, target & 0xff, target)
+            }
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::Scd { n } => format!("scroll-down {}", n),
+            Instruction::Scu { n } => format!("scroll-up {}", n),
+            Instruction::Scr => "scroll-right".to_string(),
+            Instruction::Scl => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::Low => "lores".to_string(),
+            Instruction::High => "hires".to_string(),
+            Instruction::Jp(target) => format!("jump {}", self.target(target)),
+            // A bare label is a call in Octo; a bare number would be a byte of data
+            Instruction::Call(target) if self.labels.contains_key(&target) => self.target(target),
+            Instruction::Call(target) => format!(":call {:#05x}", target),
+            // Octo's conditionals say when the next instruction runs, not when it is skipped
+            Instruction::SeVxByte { x, kk } => format!("if v{:x} != {:#04x} then", x, kk),
+            Instruction::SneVxByte { x, kk } => format!("if v{:x} == {:#04x} then", x, kk),
+            Instruction::SeVxVy { x, y } => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::SneVxVy { x, y } => format!("if v{:x} == v{:x} then", x, y),
+            Instruction::SkpVx { x } => format!("if v{:x} -key then", x),
+            Instruction::SknpVx { x } => format!("if v{:x} key then", x),
+            Instruction::SaveVxVy { x, y } => format!("save v{:x} - v{:x}", x, y),
+            Instruction::LoadVxVy { x, y } => format!("load v{:x} - v{:x}", x, y),
+            Instruction::LdVxByte { x, kk } => format!("v{:x} := {:#04x}", x, kk),
+            Instruction::AddVxByte { x, kk } => format!("v{:x} += {:#04x}", x, kk),
+            Instruction::LdVxVy { x, y } => format!("v{:x} := v{:x}", x, y),
+            Instruction::OrVxVy { x, y } => format!("v{:x} |= v{:x}", x, y),
+            Instruction::AndVxVy { x, y } => format!("v{:x} &= v{:x}", x, y),
+            Instruction::XorVxVy { x, y } => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::AddVxVy { x, y } => format!("v{:x} += v{:x}", x, y),
+            Instruction::SubVxVy { x, y } => format!("v{:x} -= v{:x}", x, y),
+            Instruction::ShrVxVy { x, y } => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::SubnVxVy { x, y } => format!("v{:x} =- v{:x}", x, y),
+            Instruction::ShlVxVy { x, y } => format!("v{:x} <<= v{:x}", x, y),
+            Instruction::LdIAddr(target) => format!("i := {}", self.target(target)),
+            Instruction::LdILong(target) => format!("i := long {}", self.target(target)),
+            Instruction::JpV0(target) => format!("jump0 {}", self.target(target)),
+            Instruction::RndVxByte { x, kk } => format!("v{:x} := random {:#04x}", x, kk),
+            Instruction::Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::Plane { n } => format!("plane {}", n),
+            Instruction::Audio => "audio".to_string(),
+            Instruction::PitchVx { x } => format!("pitch := v{:x}", x),
+            Instruction::LdVxDt { x } => format!("v{:x} := delay", x),
+            Instruction::LdVxK { x } => format!("v{:x} := key", x),
+            Instruction::LdDtVx { x } => format!("delay := v{:x}", x),
+            Instruction::LdStVx { x } => format!("buzzer := v{:x}", x),
+            Instruction::AddIVx { x } => format!("i += v{:x}", x),
+            Instruction::LdFVx { x } => format!("i := hex v{:x}", x),
+            Instruction::LdHfVx { x } => format!("i := bighex v{:x}", x),
+            Instruction::LdBVx { x } => format!("bcd v{:x}", x),
+            Instruction::LdIVx { x } => format!("save v{:x}", x),
+            Instruction::LdVxI { x } => format!("load v{:x}", x),
+            Instruction::LdRVx { x } => format!("saveflags v{:x}", x),
+            Instruction::LdVxR { x } => format!("loadflags v{:x}", x),
+        }
+    }
+
+    // Data that Annn points at is most likely a sprite, so it is drawn one byte per
+    // row. Anything else that no code path reaches is dumped eight bytes to a line.
+    fn render_data(&self, start: u16, end: u32, referenced: bool, syntax: Syntax) -> String {
+        let offset = (start - PROGRAM_START) as usize;
+        let bytes = &self.rom[offset..offset + (end - start as u32) as usize];
+        let (directive, comment) = match syntax {
+            Syntax::Octo => ("", "#"),
+            Syntax::Classic => ("db ", ";"),
+        };
+        let mut out = String::new();
+
+        if !referenced {
+            out.push_str(&format!("\t{} unreachable, {} bytes at {:#05x}\n",
+                                  comment,
+                                  bytes.len(),
+                                  start));
+        }
+
+        let row_length = if referenced { 1 } else { 8 };
+        for (row, chunk) in bytes.chunks(row_length).enumerate() {
+            let values: Vec<String> = chunk.iter().map(|b| format!("{:#04x}", b)).collect();
+            let line = match syntax {
+                Syntax::Octo => format!("\t{}{}", directive, values.join(" ")),
+                Syntax::Classic => {
+                    format!("{:#05x}  {}{}",
+                            start as usize + row * row_length,
+                            directive,
+                            values.join(", "))
+                }
+            };
+
+            if referenced {
+                let pixels: String = (0..8)
+                                         .map(|bit| {
+                                             if chunk[0] & (0x80 >> bit) != 0 { '#' } else { '.' }
+                                         })
+                                         .collect();
+                out.push_str(&format!("{}  {} {}\n", line, comment, pixels));
+            } else {
+                out.push_str(&format!("{}\n", line));
+            }
+        }
+
+        out
+    }
+}
+
+fn instruction_size(instruction: &Instruction) -> u16 {
+    match *instruction {
+        Instruction::LdILong(_) => 4,
+        _ => 2,
+    }
+}
+
+pub fn run<I: Iterator<Item = String>>(mut args: I) -> Result<(), String> {
+    let mut rom_path = None;
+    let mut syntax = Syntax::Octo;
+    let mut xo_chip = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                syntax = match args.next().as_ref().map(|s| s.as_str()) {
+                    Some("octo") => Syntax::Octo,
+                    Some("classic") => Syntax::Classic,
+                    _ => return Err("--syntax expects 'octo' or 'classic'".to_string()),
+                }
+            }
+            "--xo" => xo_chip = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    let rom_path = try!(rom_path.ok_or(USAGE.to_string()));
+    let rom = try!(Rom::from_path(Path::new(&rom_path))
+                       .map_err(|e| format!("Could not read {}: {}", rom_path, e)));
+
+    print!("{}", Disassembler::new(rom.data(), xo_chip).render(syntax));
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use sdl2::Sdl;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+const SAMPLE_RATE: i32 = 44100;
+// Length of the fade in and out applied whenever the tone starts or stops
+const ENVELOPE_SECONDS: f32 = 0.005;
+// XO-CHIP plays its 128-bit pattern at 4000 bits per second when the pitch register is 64
+const PATTERN_BASE_RATE: f32 = 4000.0;
+const PATTERN_BITS: f32 = 128.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sine" => Some(Waveform::Sine),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+
+    // Sample at a phase in [0, 1), in the range [-1, 1]
+    fn sample(&self, phase: f32) -> f32 {
+        match *self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sine => (phase * 2.0 * ::std::f32::consts::PI).sin(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioSettings {
+    pub frequency: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for AudioSettings {
+    fn default() -> AudioSettings {
+        AudioSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+struct ToneGenerator {
+    settings: AudioSettings,
+    phase: f32,
+    gain: f32,
+    target_gain: f32,
+    gain_step: f32,
+    pattern: Option<[u8; 16]>,
+    pattern_rate: f32,
+}
+
+impl ToneGenerator {
+    fn next_sample(&mut self) -> f32 {
+        let value = match self.pattern {
+            Some(ref pattern) => {
+                let bit = (self.phase * PATTERN_BITS) as usize;
+                if (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            None => self.settings.waveform.sample(self.phase),
+        };
+
+        let cycles_per_second = match self.pattern {
+            Some(_) => self.pattern_rate / PATTERN_BITS,
+            None => self.settings.frequency,
+        };
+        self.phase = (self.phase + cycles_per_second / SAMPLE_RATE as f32) % 1.0;
+
+        if self.gain < self.target_gain {
+            self.gain = (self.gain + self.gain_step).min(self.target_gain);
+        } else if self.gain > self.target_gain {
+            self.gain = (self.gain - self.gain_step).max(self.target_gain);
+        }
+
+        value * self.gain * self.settings.volume
+    }
+}
+
+impl AudioCallback for ToneGenerator {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+}
+
+// The device keeps running while silent, so starting and stopping the tone only
+// moves the envelope's target and never cuts the waveform off mid-cycle. SDL pulls
+// samples through a callback rather than an audio queue the emulator fills each
+// frame: a queue runs dry whenever frames stop coming (paused, stopped in the
+// debugger or waiting on gdb) and the tone would end in a click instead of fading.
+pub struct Audio {
+    device: AudioDevice<ToneGenerator>,
+}
+
+impl Audio {
+    pub fn new(sdl_context: &Sdl, settings: AudioSettings) -> Result<Audio, String> {
+        let audio_subsystem = try!(sdl_context.audio());
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = try!(audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            ToneGenerator {
+                settings: settings,
+                phase: 0.0,
+                gain: 0.0,
+                target_gain: 0.0,
+                gain_step: 1.0 / (ENVELOPE_SECONDS * spec.freq as f32),
+                pattern: None,
+                pattern_rate: PATTERN_BASE_RATE,
+            }
+        }));
+        device.resume();
+
+        Ok(Audio { device: device })
+    }
+
+    pub fn start(&mut self) {
+        self.device.lock().target_gain = 1.0;
+    }
+
+    pub fn stop(&mut self) {
+        self.device.lock().target_gain = 0.0;
+    }
+
+    // Without a pattern the tone goes back to the configured waveform
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        let mut generator = self.device.lock();
+        generator.pattern = pattern.cloned();
+        generator.pattern_rate = PATTERN_BASE_RATE * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+    }
+}
@@ -0,0 +1,133 @@
+use super::InputEvent;
+use super::keymap;
+
+// Buttons by their SDL game controller names
+pub const BUTTON_NAMES: [&'static str; 15] = ["a", "b", "x", "y", "back", "guide", "start",
+                                              "leftstick", "rightstick", "leftshoulder",
+                                              "rightshoulder", "dpup", "dpdown", "dpleft",
+                                              "dpright"];
+
+pub const HOTKEY_NAMES: [&'static str; 7] = ["pause", "save_state", "load_state", "next_slot",
+                                             "previous_slot", "rewind", "quit"];
+
+// The directions go to the keys around 5, which most games use for movement
+const DEFAULT_LAYOUT: [(&'static str, &'static str); 12] = [("dpup", "2"), ("dpdown", "8"),
+                                                           ("dpleft", "4"), ("dpright", "6"),
+                                                           ("a", "5"), ("b", "0"), ("x", "a"),
+                                                           ("y", "b"), ("leftshoulder", "c"),
+                                                           ("rightshoulder", "d"),
+                                                           ("start", "pause"),
+                                                           ("back", "rewind")];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Pause,
+    SaveState,
+    LoadState,
+    NextStateSlot,
+    PreviousStateSlot,
+    // Held like Backspace
+    Rewind,
+    Quit,
+}
+
+impl Hotkey {
+    pub fn from_name(name: &str) -> Option<Hotkey> {
+        match name {
+            "pause" => Some(Hotkey::Pause),
+            "save_state" => Some(Hotkey::SaveState),
+            "load_state" => Some(Hotkey::LoadState),
+            "next_slot" => Some(Hotkey::NextStateSlot),
+            "previous_slot" => Some(Hotkey::PreviousStateSlot),
+            "rewind" => Some(Hotkey::Rewind),
+            "quit" => Some(Hotkey::Quit),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    Key(u8),
+    Hotkey(Hotkey),
+}
+
+impl ButtonAction {
+    // A CHIP-8 key as one hex digit, or a hotkey name
+    pub fn from_name(name: &str) -> Option<ButtonAction> {
+        keymap::parse_key(name)
+            .map(ButtonAction::Key)
+            .or_else(|| Hotkey::from_name(name).map(ButtonAction::Hotkey))
+    }
+
+    pub fn press(&self) -> Option<InputEvent> {
+        match *self {
+            ButtonAction::Key(key) => Some(InputEvent::KeyDown(key)),
+            ButtonAction::Hotkey(Hotkey::Pause) => Some(InputEvent::Pause),
+            ButtonAction::Hotkey(Hotkey::SaveState) => Some(InputEvent::SaveState),
+            ButtonAction::Hotkey(Hotkey::LoadState) => Some(InputEvent::LoadState),
+            ButtonAction::Hotkey(Hotkey::NextStateSlot) => Some(InputEvent::NextStateSlot),
+            ButtonAction::Hotkey(Hotkey::PreviousStateSlot) => {
+                Some(InputEvent::PreviousStateSlot)
+            }
+            ButtonAction::Hotkey(Hotkey::Rewind) => Some(InputEvent::RewindStart),
+            ButtonAction::Hotkey(Hotkey::Quit) => Some(InputEvent::Quit),
+        }
+    }
+
+    pub fn release(&self) -> Option<InputEvent> {
+        match *self {
+            ButtonAction::Key(key) => Some(InputEvent::KeyUp(key)),
+            ButtonAction::Hotkey(Hotkey::Rewind) => Some(InputEvent::RewindStop),
+            ButtonAction::Hotkey(_) => None,
+        }
+    }
+}
+
+// What each controller button does. Every connected controller shares it.
+#[derive(Clone)]
+pub struct ControllerMap {
+    bindings: Vec<(String, ButtonAction)>,
+}
+
+impl ControllerMap {
+    // Replaces the button's action; "none" leaves the button unbound
+    pub fn bind(&mut self, button: &str, action: &str) -> Result<(), String> {
+        if !BUTTON_NAMES.contains(&button) {
+            return Err(format!("Unknown controller button '{}', expected one of: {}",
+                               button,
+                               BUTTON_NAMES.join(", ")));
+        }
+
+        self.bindings.retain(|&(ref bound, _)| bound != button);
+        if action == "none" {
+            return Ok(());
+        }
+
+        let action = try!(ButtonAction::from_name(action)
+                              .ok_or(format!("Invalid action '{}' for button {}, expected a \
+                                              CHIP-8 key 0-F, none or one of: {}",
+                                             action,
+                                             button,
+                                             HOTKEY_NAMES.join(", "))));
+        self.bindings.push((button.to_string(), action));
+        Ok(())
+    }
+
+    pub fn bindings(&self) -> &[(String, ButtonAction)] {
+        &self.bindings
+    }
+}
+
+impl Default for ControllerMap {
+    fn default() -> ControllerMap {
+        ControllerMap {
+            bindings: DEFAULT_LAYOUT.iter()
+                                    .map(|&(button, action)| {
+                                        (button.to_string(),
+                                         ButtonAction::from_name(action).unwrap())
+                                    })
+                                    .collect(),
+        }
+    }
+}
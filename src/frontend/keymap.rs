@@ -0,0 +1,118 @@
+// Which host keys press which CHIP-8 keys. Host keys are given by their SDL names,
+// such as "W", "Up" or "Keypad 5", so the table can be built before SDL starts.
+// A name after SCANCODE_PREFIX is an SDL scancode instead, which picks a key by its
+// position whatever it types.
+
+pub const PROFILE_NAMES: [&'static str; 4] = ["qwerty", "qwertz", "azerty", "numpad"];
+
+pub const SCANCODE_PREFIX: &'static str = "Scancode ";
+
+// The hex keypad is laid out as
+//
+//     1 2 3 C
+//     4 5 6 D
+//     7 8 9 E
+//     A 0 B F
+//
+// and the keyboard profiles put it on the block of keys under 1234.
+const QWERTY: [(u8, &'static str); 16] = [(0x1, "1"), (0x2, "2"), (0x3, "3"), (0xc, "4"),
+                                          (0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xd, "R"),
+                                          (0x7, "A"), (0x8, "S"), (0x9, "D"), (0xe, "F"),
+                                          (0xa, "Z"), (0x0, "X"), (0xb, "C"), (0xf, "V")];
+
+const QWERTZ: [(u8, &'static str); 16] = [(0x1, "1"), (0x2, "2"), (0x3, "3"), (0xc, "4"),
+                                          (0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xd, "R"),
+                                          (0x7, "A"), (0x8, "S"), (0x9, "D"), (0xe, "F"),
+                                          (0xa, "Y"), (0x0, "X"), (0xb, "C"), (0xf, "V")];
+
+// The number row types &, é, " and ' unless shifted, and SDL has no keycode for é, so
+// it is bound by position. Scancodes are named after the US layout.
+const AZERTY: [(u8, &'static str); 16] = [(0x1, "Scancode 1"), (0x2, "Scancode 2"),
+                                          (0x3, "Scancode 3"), (0xc, "Scancode 4"),
+                                          (0x4, "A"), (0x5, "Z"), (0x6, "E"), (0xd, "R"),
+                                          (0x7, "Q"), (0x8, "S"), (0x9, "D"), (0xe, "F"),
+                                          (0xa, "W"), (0x0, "X"), (0xb, "C"), (0xf, "V")];
+
+// Digits on the keys with the same digit, A-F on the keys around them
+const NUMPAD: [(u8, &'static str); 16] = [(0x0, "Keypad 0"), (0x1, "Keypad 1"),
+                                          (0x2, "Keypad 2"), (0x3, "Keypad 3"),
+                                          (0x4, "Keypad 4"), (0x5, "Keypad 5"),
+                                          (0x6, "Keypad 6"), (0x7, "Keypad 7"),
+                                          (0x8, "Keypad 8"), (0x9, "Keypad 9"),
+                                          (0xa, "Keypad /"), (0xb, "Keypad *"),
+                                          (0xc, "Keypad -"), (0xd, "Keypad +"),
+                                          (0xe, "Keypad Enter"), (0xf, "Keypad .")];
+
+#[derive(Clone, Default)]
+pub struct Keymap {
+    // Host key name and the CHIP-8 key it presses. A CHIP-8 key can have several.
+    bindings: Vec<(String, u8)>,
+}
+
+impl Keymap {
+    pub fn from_profile(name: &str) -> Option<Keymap> {
+        let table: &[(u8, &str)] = match name {
+            "qwerty" => &QWERTY,
+            "qwertz" => &QWERTZ,
+            "azerty" => &AZERTY,
+            "numpad" => &NUMPAD,
+            _ => return None,
+        };
+
+        let mut keymap = Keymap::default();
+        for &(key, host_key) in table {
+            keymap.bind(key, host_key);
+        }
+        Some(keymap)
+    }
+
+    // A host key only ever presses one CHIP-8 key, so this replaces any earlier binding
+    pub fn bind(&mut self, key: u8, host_key: &str) {
+        self.bindings.retain(|&(ref bound, _)| bound != host_key);
+        self.bindings.push((host_key.to_string(), key));
+    }
+
+    // Rebinds every CHIP-8 key mentioned to just the host keys given for it here
+    pub fn apply(&mut self, bindings: &[(u8, String)]) {
+        self.bindings.retain(|&(_, key)| !bindings.iter().any(|&(rebound, _)| rebound == key));
+        for &(key, ref host_key) in bindings {
+            self.bind(key, host_key);
+        }
+    }
+
+    pub fn bindings(&self) -> &[(String, u8)] {
+        &self.bindings
+    }
+}
+
+// A CHIP-8 key written as one hex digit
+pub fn parse_key(text: &str) -> Option<u8> {
+    if text.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_profile_binds_every_key() {
+        for name in PROFILE_NAMES.iter() {
+            let keymap = Keymap::from_profile(name).unwrap();
+            for key in 0..16 {
+                assert!(keymap.bindings().iter().any(|&(_, bound)| bound == key),
+                        "{} leaves key {:x} unbound",
+                        name,
+                        key);
+            }
+        }
+    }
+
+    #[test]
+    fn azerty_binds_the_number_row_by_position() {
+        let keymap = Keymap::from_profile("azerty").unwrap();
+        assert!(keymap.bindings().contains(&("Scancode 2".to_string(), 0x2)));
+    }
+}
@@ -0,0 +1,33 @@
+pub mod audio;
+pub mod controller;
+pub mod keymap;
+pub mod sdl;
+
+use cpu::display::Display;
+
+pub enum InputEvent {
+    Quit,
+    KeyDown(u8),
+    KeyUp(u8),
+    Step,
+    Pause,
+    DumpMemory,
+    SaveState,
+    LoadState,
+    NextStateSlot,
+    PreviousStateSlot,
+    RewindStart,
+    RewindStop,
+}
+
+pub trait Frontend {
+    fn present(&mut self, framebuffer: &Display);
+
+    fn poll_input(&mut self) -> Vec<InputEvent>;
+
+    fn start_tone(&mut self);
+
+    fn stop_tone(&mut self);
+
+    fn set_audio_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8);
+}
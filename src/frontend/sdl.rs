@@ -0,0 +1,269 @@
+use super::{Frontend, InputEvent};
+use super::audio::{Audio, AudioSettings};
+use super::controller::{ButtonAction, ControllerMap};
+use super::keymap::{self, Keymap};
+
+use cpu::display::Display;
+
+use sdl2;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::render::Renderer;
+use sdl2::{EventPump, GameControllerSubsystem};
+
+use std::collections::HashMap;
+
+const WINDOW_WIDTH: u32 = 640;
+const WINDOW_HEIGHT: u32 = 320;
+
+// Colours for each combination of the two XO-CHIP bitplanes
+const PALETTE: [(u8, u8, u8); 4] = [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)];
+
+pub struct SdlFrontend<'a> {
+    sdl_event_pump: EventPump,
+    window: Renderer<'a>,
+    audio: Option<Audio>,
+    palette: [(u8, u8, u8); 4],
+    // Empty until `set_keymap`
+    keymap: HashMap<Keycode, u8>,
+    scancode_keymap: HashMap<Scancode, u8>,
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // Controllers are opened as SDL reports them connected
+    controllers: Vec<GameController>,
+    // Empty until `set_controller_map`
+    controller_map: HashMap<Button, ButtonAction>,
+}
+
+impl<'a> SdlFrontend<'a> {
+    pub fn new(audio_settings: AudioSettings) -> SdlFrontend<'a> {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let new_window = video_subsystem.window("Rust8", WINDOW_WIDTH, WINDOW_HEIGHT)
+                                        .position_centered()
+                                        .opengl()
+                                        .build()
+                                        .unwrap();
+
+        let mut renderer = new_window.renderer().build().unwrap();
+
+        renderer.set_draw_color(Color::RGB(0, 0, 0));
+        renderer.clear();
+        renderer.present();
+        renderer.set_draw_color(Color::RGB(255, 255, 255));
+
+        // Carry on without sound rather than refusing to run on a machine with no audio device
+        let audio = match Audio::new(&sdl_context, audio_settings) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                println!("Audio disabled: {}", e);
+                None
+            }
+        };
+
+        // SDL reports controllers that are already plugged in as connected once events are polled
+        let controller_subsystem = match sdl_context.game_controller() {
+            Ok(subsystem) => Some(subsystem),
+            Err(e) => {
+                println!("Game controllers disabled: {}", e);
+                None
+            }
+        };
+
+        SdlFrontend {
+            sdl_event_pump: sdl_context.event_pump().unwrap(),
+            window: renderer,
+            audio: audio,
+            palette: PALETTE,
+            keymap: HashMap::new(),
+            scancode_keymap: HashMap::new(),
+            controller_subsystem: controller_subsystem,
+            controllers: Vec::new(),
+            controller_map: HashMap::new(),
+        }
+    }
+
+    // Replaces the first colours of the palette, so two colours leave the extra
+    // XO-CHIP plane colours alone
+    pub fn set_palette(&mut self, colors: &[(u8, u8, u8)]) {
+        for (entry, &color) in self.palette.iter_mut().zip(colors) {
+            *entry = color;
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: &Keymap) -> Result<(), String> {
+        let mut keycodes = HashMap::new();
+        let mut scancodes = HashMap::new();
+        for &(ref name, key) in keymap.bindings() {
+            if name.starts_with(keymap::SCANCODE_PREFIX) {
+                let scancode = try!(Scancode::from_name(&name[keymap::SCANCODE_PREFIX.len()..])
+                                        .ok_or(format!("Unknown scancode '{}'", name)));
+                scancodes.insert(scancode, key);
+            } else {
+                let keycode = try!(Keycode::from_name(name)
+                                       .ok_or(format!("Unknown key '{}'", name)));
+                keycodes.insert(keycode, key);
+            }
+        }
+        self.keymap = keycodes;
+        self.scancode_keymap = scancodes;
+        Ok(())
+    }
+
+    pub fn set_controller_map(&mut self, map: &ControllerMap) -> Result<(), String> {
+        let mut buttons = HashMap::new();
+        for &(ref name, action) in map.bindings() {
+            let button = try!(Button::from_string(name)
+                                  .ok_or(format!("Unknown controller button '{}'", name)));
+            buttons.insert(button, action);
+        }
+        self.controller_map = buttons;
+        Ok(())
+    }
+
+    fn open_controller(&mut self, index: u32) {
+        if let Some(ref subsystem) = self.controller_subsystem {
+            match subsystem.open(index) {
+                Ok(controller) => {
+                    println!("Controller connected: {}", controller.name());
+                    self.controllers.push(controller);
+                }
+                Err(e) => println!("Could not open controller {}: {:?}", index, e),
+            }
+        }
+    }
+
+    fn close_detached_controllers(&mut self) {
+        let (attached, detached): (Vec<_>, Vec<_>) = self.controllers
+                                                         .drain(..)
+                                                         .partition(|c| c.attached());
+        for controller in detached {
+            println!("Controller disconnected: {}", controller.name());
+        }
+        self.controllers = attached;
+    }
+}
+
+impl<'a> Frontend for SdlFrontend<'a> {
+    fn present(&mut self, framebuffer: &Display) {
+        let mut rect_vecs: [Vec<Rect>; 4] = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+
+        // Scale whichever resolution is active to fill the window
+        let pixel_width = WINDOW_WIDTH / framebuffer.width() as u32;
+        let pixel_height = WINDOW_HEIGHT / framebuffer.height() as u32;
+
+        for x in 0..framebuffer.width() {
+            for y in 0..framebuffer.height() {
+                let rect = Rect::new_unwrap((x as u32 * pixel_width) as i32,
+                                            (y as u32 * pixel_height) as i32,
+                                            pixel_width,
+                                            pixel_height);
+                rect_vecs[framebuffer.pixel(x, y) as usize].push(rect);
+            }
+        }
+
+        for (colour, rects) in rect_vecs.iter().enumerate() {
+            let (r, g, b) = self.palette[colour];
+            self.window.set_draw_color(Color::RGB(r, g, b));
+
+            for rect in rects {
+                self.window.fill_rect(*rect);
+            }
+        }
+
+        self.window.present();
+    }
+
+    fn poll_input(&mut self) -> Vec<InputEvent> {
+        let mut input_events = Vec::new();
+
+        let events: Vec<Event> = self.sdl_event_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), .. } => {
+                    input_events.push(InputEvent::Quit);
+                }
+                // Bound keys win over the hotkeys below, so any key can be used for play
+                Event::KeyDown {keycode: Some(code), ..} if self.keymap.contains_key(&code) => {
+                    input_events.push(InputEvent::KeyDown(self.keymap[&code]));
+                }
+                Event::KeyUp {keycode: Some(code), ..} if self.keymap.contains_key(&code) => {
+                    input_events.push(InputEvent::KeyUp(self.keymap[&code]));
+                }
+                Event::KeyDown {scancode: Some(code), ..}
+                    if self.scancode_keymap.contains_key(&code) => {
+                    input_events.push(InputEvent::KeyDown(self.scancode_keymap[&code]));
+                }
+                Event::KeyUp {scancode: Some(code), ..}
+                    if self.scancode_keymap.contains_key(&code) => {
+                    input_events.push(InputEvent::KeyUp(self.scancode_keymap[&code]));
+                }
+                Event::KeyDown {keycode: Some(Keycode::K), ..} => {
+                    input_events.push(InputEvent::Step);
+                }
+                Event::KeyDown {keycode: Some(Keycode::P), ..} => {
+                    input_events.push(InputEvent::Pause);
+                }
+                Event::KeyDown {keycode: Some(Keycode::M), ..} => {
+                    input_events.push(InputEvent::DumpMemory);
+                }
+                Event::KeyDown {keycode: Some(Keycode::F5), ..} => {
+                    input_events.push(InputEvent::SaveState);
+                }
+                Event::KeyDown {keycode: Some(Keycode::F6), ..} => {
+                    input_events.push(InputEvent::PreviousStateSlot);
+                }
+                Event::KeyDown {keycode: Some(Keycode::F7), ..} => {
+                    input_events.push(InputEvent::NextStateSlot);
+                }
+                Event::KeyDown {keycode: Some(Keycode::F9), ..} => {
+                    input_events.push(InputEvent::LoadState);
+                }
+                Event::KeyDown {keycode: Some(Keycode::Backspace), ..} => {
+                    input_events.push(InputEvent::RewindStart);
+                }
+                Event::KeyUp {keycode: Some(Keycode::Backspace), ..} => {
+                    input_events.push(InputEvent::RewindStop);
+                }
+                Event::ControllerDeviceAdded {which, ..} => {
+                    self.open_controller(which as u32);
+                }
+                Event::ControllerDeviceRemoved {..} => {
+                    self.close_detached_controllers();
+                }
+                Event::ControllerButtonDown {button, ..} => {
+                    let event = self.controller_map.get(&button).and_then(|a| a.press());
+                    input_events.extend(event);
+                }
+                Event::ControllerButtonUp {button, ..} => {
+                    let event = self.controller_map.get(&button).and_then(|a| a.release());
+                    input_events.extend(event);
+                }
+                _ => {}
+            }
+        }
+
+        input_events
+    }
+
+    fn start_tone(&mut self) {
+        if let Some(ref mut audio) = self.audio {
+            audio.start();
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        if let Some(ref mut audio) = self.audio {
+            audio.stop();
+        }
+    }
+
+    fn set_audio_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        if let Some(ref mut audio) = self.audio {
+            audio.set_pattern(pattern, pitch);
+        }
+    }
+}
@@ -2,35 +2,137 @@ extern crate sdl2;
 extern crate time;
 extern crate rand;
 
+mod asm;
+mod config;
 mod cpu;
+mod disasm;
+mod frontend;
+mod movie;
+mod number;
+mod options;
+mod romdb;
 
 use std::env;
-use std::fs::File;
+use std::path::Path;
+use std::process;
 use cpu::cpu::Chip8;
+use frontend::sdl::SdlFrontend;
+use movie::Movie;
+use options::Options;
 
 fn main() {
-    let program_path = env::args().nth(1).unwrap();
-    println!("ROM path: {}", program_path);
+    let mut args = env::args().skip(1).peekable();
+    let tool: Option<fn(_) -> Result<(), String>> = match args.peek().map(|a| a.as_str()) {
+        Some("disasm") => Some(disasm::run),
+        Some("asm") => Some(asm::run),
+        _ => None,
+    };
+    if let Some(tool) = tool {
+        args.next();
+        if let Err(e) = tool(args) {
+            println!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    let rom_file = File::open(program_path).unwrap();
-    println!("Opened file");
-    let mut chip8_emu = Chip8::new();
+    let options = match Options::parse(args) {
+        Ok(o) => o,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    println!("ROM path: {}", options.rom_path);
+    println!("Quirks: {}", options.quirks);
+    let seed = options.seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
 
-    chip8_emu.store_program_data(rom_file);
+    println!("ROM: {}", options.rom);
+    if let Some(ref title) = options.rom_info.title {
+        match options.rom_info.author {
+            Some(ref author) => println!("Title: {} by {}", title, author),
+            None => println!("Title: {}", title),
+        }
+    }
 
-    chip8_emu.init_display();
+    let mut frontend = SdlFrontend::new(options.audio);
+    frontend.set_palette(&options.colors);
+    if let Err(e) = frontend.set_keymap(&options.keymap) {
+        println!("Could not set up the keymap: {}", e);
+        process::exit(1);
+    }
+    if let Err(e) = frontend.set_controller_map(&options.controller_map) {
+        println!("Could not set up the controller: {}", e);
+        process::exit(1);
+    }
+    let mut chip8_emu = Chip8::new(frontend);
+    chip8_emu.set_key_wait_policy(options.key_wait_policy);
+    chip8_emu.set_quirks(options.quirks);
+    chip8_emu.set_instructions_per_frame(options.instructions_per_frame);
+    chip8_emu.set_state_prefix(&options.rom_path);
+    chip8_emu.set_rewind_seconds(options.rewind_seconds);
+    chip8_emu.set_random(options.random_mode, seed);
+    chip8_emu.set_fonts(options.fonts.clone());
+    if options.schip {
+        chip8_emu.enable_schip();
+    }
+    if options.xo_chip {
+        chip8_emu.enable_xo_chip();
+    }
 
-    let debug_arg = env::args().nth(2);
-    match debug_arg {
-        Some(d) => {
-            if &d == "-d" {
-                chip8_emu._run_debug();
-            } else {
-                chip8_emu.run();
+    if let Some(ref path) = options.play_movie {
+        match Movie::load(Path::new(path)) {
+            Ok(movie) => {
+                let seed = movie.header.seed;
+                if let Err(e) = chip8_emu.play_movie(movie) {
+                    println!("Could not play {}: {}", path, e);
+                    process::exit(1);
+                }
+                println!("Playing movie {} (seed {})", path, seed);
             }
+            Err(e) => {
+                println!("Could not load {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = chip8_emu.load_rom(&options.rom) {
+        println!("Could not load ROM: {}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = chip8_emu.init_display() {
+        println!("Could not load fonts: {}", e);
+        process::exit(1);
+    }
+
+    if let Some(ref path) = options.trace_path {
+        if let Err(e) = chip8_emu.trace_to(Path::new(path), options.trace_filter.clone()) {
+            println!("Could not trace to {}: {}", path, e);
+            process::exit(1);
         }
-        None => {
-            chip8_emu.run();
+    }
+
+    if let Some(ref path) = options.record_movie {
+        if let Err(e) = chip8_emu.record_movie(Path::new(path)) {
+            println!("Could not record to {}: {}", path, e);
+            process::exit(1);
         }
     }
+
+    let result = if let Some(port) = options.gdb_port {
+        chip8_emu.run_gdb(port)
+    } else if options.debug {
+        chip8_emu.run_debugger()
+    } else {
+        chip8_emu.run()
+    };
+
+    if let Err(e) = result {
+        println!("Chip8 status at end time: {:#?}", chip8_emu);
+        println!("Emulation stopped: {}", e);
+        process::exit(1);
+    }
 }
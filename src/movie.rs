@@ -0,0 +1,210 @@
+use cpu::error::EmuError;
+use cpu::keypad::KeyWaitPolicy;
+use cpu::quirks::{self, Quirks};
+use cpu::random::RandomMode;
+
+use config::Config;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// Movies use the same format as configuration files. The header holds everything
+// that affects emulation, and the [input] section lists keypad changes by frame:
+//
+//     rom_crc32 = 0a1b2c3d
+//     load_addr = 200
+//     font_crc32 = 4e5f6a7b
+//     seed = 1234
+//     rng = xorshift
+//     key_wait = release
+//     ipf = 10
+//     schip = off
+//     xo_chip = off
+//
+//     [quirks]
+//     shift_uses_vy = on
+//
+//     [input]
+//     120 = down 5
+//     126 = up 5
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieHeader {
+    pub rom_crc32: u32,
+    pub load_addr: u16,
+    pub font_crc32: u32,
+    pub seed: u32,
+    pub random_mode: RandomMode,
+    pub key_wait_policy: KeyWaitPolicy,
+    pub instructions_per_frame: usize,
+    pub schip: bool,
+    pub xo_chip: bool,
+    pub quirks: Quirks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieEvent {
+    pub frame: u32,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+pub struct Movie {
+    pub header: MovieHeader,
+    pub events: Vec<MovieEvent>,
+}
+
+impl Movie {
+    pub fn load(path: &Path) -> Result<Movie, EmuError> {
+        let config = try!(Config::load(path).map_err(EmuError::InvalidMovie));
+
+        let rom_crc32 = try!(required(&config, "rom_crc32"));
+        let rom_crc32 = try!(u32::from_str_radix(rom_crc32, 16)
+                                 .map_err(|_| invalid(format!("bad rom_crc32 '{}'", rom_crc32))));
+
+        let load_addr = try!(required(&config, "load_addr"));
+        let load_addr = try!(u16::from_str_radix(load_addr, 16)
+                                 .map_err(|_| invalid(format!("bad load_addr '{}'", load_addr))));
+
+        let font_crc32 = try!(required(&config, "font_crc32"));
+        let font_crc32 = try!(u32::from_str_radix(font_crc32, 16).map_err(|_| {
+            invalid(format!("bad font_crc32 '{}'", font_crc32))
+        }));
+
+        let seed = try!(required(&config, "seed"));
+        let seed = try!(seed.parse::<u32>().map_err(|_| invalid(format!("bad seed '{}'", seed))));
+
+        let rng = try!(required(&config, "rng"));
+        let random_mode = try!(RandomMode::from_name(rng)
+                                   .ok_or(invalid(format!("unknown generator '{}'", rng))));
+
+        let key_wait = try!(required(&config, "key_wait"));
+        let key_wait_policy = try!(KeyWaitPolicy::from_name(key_wait)
+                                       .ok_or(invalid(format!("bad key_wait '{}'", key_wait))));
+
+        let ipf = try!(required(&config, "ipf"));
+        let instructions_per_frame = try!(ipf.parse::<usize>()
+                                             .ok()
+                                             .and_then(|n| if n > 0 { Some(n) } else { None })
+                                             .ok_or(invalid(format!("bad ipf '{}'", ipf))));
+
+        let schip = try!(quirks::parse_bool(try!(required(&config, "schip"))).map_err(invalid));
+        let xo_chip = try!(quirks::parse_bool(try!(required(&config, "xo_chip")))
+                               .map_err(invalid));
+
+        let mut quirks = Quirks::default();
+        for entry in config.section("quirks") {
+            let value = try!(quirks::parse_bool(&entry.value)
+                                 .map_err(|e| invalid(format!("line {}: {}", entry.line, e))));
+            try!(quirks.set(&entry.key, value)
+                       .map_err(|e| invalid(format!("line {}: {}", entry.line, e))));
+        }
+
+        let mut events = Vec::new();
+        for entry in config.section("input") {
+            let event = try!(parse_event(&entry.key, &entry.value)
+                                 .ok_or(invalid(format!("line {}: expected '<frame> = down|up \
+                                                         <key>'",
+                                                        entry.line))));
+            if events.last().map_or(false, |last: &MovieEvent| last.frame > event.frame) {
+                return Err(invalid(format!("line {}: frames are out of order", entry.line)));
+            }
+            events.push(event);
+        }
+
+        Ok(Movie {
+            header: MovieHeader {
+                rom_crc32: rom_crc32,
+                load_addr: load_addr,
+                font_crc32: font_crc32,
+                seed: seed,
+                random_mode: random_mode,
+                key_wait_policy: key_wait_policy,
+                instructions_per_frame: instructions_per_frame,
+                schip: schip,
+                xo_chip: xo_chip,
+                quirks: quirks,
+            },
+            events: events,
+        })
+    }
+}
+
+// Writes each event as it happens, so the movie survives the emulator crashing
+pub struct MovieRecorder {
+    file: File,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &Path, header: &MovieHeader) -> Result<MovieRecorder, EmuError> {
+        let mut file = try!(File::create(path));
+
+        try!(writeln!(file, "rom_crc32 = {:08x}", header.rom_crc32));
+        try!(writeln!(file, "load_addr = {:03x}", header.load_addr));
+        try!(writeln!(file, "font_crc32 = {:08x}", header.font_crc32));
+        try!(writeln!(file, "seed = {}", header.seed));
+        try!(writeln!(file, "rng = {}", header.random_mode.name()));
+        try!(writeln!(file, "key_wait = {}", header.key_wait_policy.name()));
+        try!(writeln!(file, "ipf = {}", header.instructions_per_frame));
+        try!(writeln!(file, "schip = {}", on_off(header.schip)));
+        try!(writeln!(file, "xo_chip = {}", on_off(header.xo_chip)));
+
+        try!(writeln!(file, "\n[quirks]"));
+        for name in quirks::QUIRK_NAMES.iter() {
+            let value = header.quirks.get(name).unwrap_or(false);
+            try!(writeln!(file, "{} = {}", name, on_off(value)));
+        }
+
+        try!(writeln!(file, "\n[input]"));
+
+        Ok(MovieRecorder { file: file })
+    }
+
+    pub fn record(&mut self, event: &MovieEvent) -> Result<(), EmuError> {
+        try!(writeln!(self.file,
+                      "{} = {} {:x}",
+                      event.frame,
+                      if event.pressed { "down" } else { "up" },
+                      event.key));
+        Ok(())
+    }
+}
+
+fn required<'a>(config: &'a Config, key: &str) -> Result<&'a str, EmuError> {
+    config.get("", key).ok_or(invalid(format!("missing '{}'", key)))
+}
+
+fn invalid(reason: String) -> EmuError {
+    EmuError::InvalidMovie(reason)
+}
+
+fn parse_event(frame: &str, action: &str) -> Option<MovieEvent> {
+    let frame = match frame.parse::<u32>() {
+        Ok(f) => f,
+        Err(_) => return None,
+    };
+
+    let mut parts = action.split_whitespace();
+    let pressed = match parts.next() {
+        Some("down") => true,
+        Some("up") => false,
+        _ => return None,
+    };
+    let key = match parts.next().and_then(|k| u8::from_str_radix(k, 16).ok()) {
+        Some(k) if k < 16 => k,
+        _ => return None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(MovieEvent {
+        frame: frame,
+        key: key,
+        pressed: pressed,
+    })
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
@@ -0,0 +1,41 @@
+// Numbers in options, debugger commands and assembly source are decimal unless
+// written with 0x for hex or 0b for binary
+pub fn parse_number(text: &str) -> Option<usize> {
+    let result = if text.starts_with("0x") || text.starts_with("0X") {
+        usize::from_str_radix(&text[2..], 16)
+    } else if text.starts_with("0b") || text.starts_with("0B") {
+        usize::from_str_radix(&text[2..], 2)
+    } else {
+        text.parse::<usize>()
+    };
+    result.ok()
+}
+
+// A number that fits in the 16-bit address space
+pub fn parse_address(text: &str) -> Option<u16> {
+    match parse_number(text) {
+        Some(value) if value <= 0xffff => Some(value as u16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_decimal_hex_or_binary() {
+        assert_eq!(parse_number("512"), Some(512));
+        assert_eq!(parse_number("0x200"), Some(0x200));
+        assert_eq!(parse_number("0XfF"), Some(0xff));
+        assert_eq!(parse_number("0b101"), Some(5));
+        assert_eq!(parse_number("0x"), None);
+        assert_eq!(parse_number("v0"), None);
+    }
+
+    #[test]
+    fn addresses_fit_in_16_bits() {
+        assert_eq!(parse_address("0xffff"), Some(0xffff));
+        assert_eq!(parse_address("0x10000"), None);
+    }
+}
@@ -0,0 +1,547 @@
+use cpu::breakpoint::OpcodePattern;
+use cpu::cpu::DEFAULT_REWIND_SECONDS;
+use cpu::font::FontSet;
+use cpu::keypad::KeyWaitPolicy;
+use cpu::machine::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use cpu::quirks::{self, Quirks};
+use cpu::random::RandomMode;
+use cpu::rom::{Rom, DEFAULT_LOAD_ADDR};
+use cpu::trace::TraceFilter;
+
+use config::Config;
+use frontend::audio::{AudioSettings, Waveform};
+use frontend::controller::ControllerMap;
+use frontend::keymap::{self, Keymap};
+use number::parse_address;
+use romdb::{RomDatabase, RomInfo};
+
+use std::path::Path;
+
+pub const USAGE: &'static str = "Usage: chip8 <rom> [options]
+       chip8 disasm <rom> [--syntax octo|classic] [--xo]
+       chip8 asm <source> -o <rom> [--xo]
+
+Options:
+    -d                        Start paused in the command-line debugger
+    --gdb <port>              Wait for a GDB remote protocol connection on
+                              127.0.0.1:<port> and start paused under it
+    --ipf <n>                 Instructions executed per 60 Hz frame (default 10)
+    --load-addr <addr>        Where the ROM is loaded and started (default
+                              0x200; ETI-660 programs use 0x600)
+    --config <path>           Read settings from a configuration file
+    --romdb <path>            Read ROM metadata that overrides the bundled
+                              database (may be repeated)
+    --platform <name>         Quirks preset: cosmac_vip, chip48, schip_legacy,
+                              schip_modern or xo_chip. xo_chip also enables
+                              64 KiB of memory and the XO-CHIP instructions.
+                              The schip_* and xo_chip presets allow the
+                              SUPER-CHIP instructions. Without a preset every
+                              quirk starts off
+    --quirk <name>=<on|off>   Override a single quirk (may be repeated)
+    --keymap <name>           Keyboard layout: qwerty, qwertz, azerty, numpad,
+                              or a [keymap.<name>] section of the configuration
+    --bind <key>=<host key>   Bind a host key, such as W or Up, to a CHIP-8 key
+                              0-F, replacing its profile keys (may be repeated).
+                              Scancode <name> binds a key by its position, as
+                              named on a US keyboard
+    --button <button>=<action>
+                              Bind a game controller button (a, b, x, y, back,
+                              start, leftshoulder, dpup, ...) to a CHIP-8 key
+                              0-F, none, or pause, save_state, load_state,
+                              next_slot, previous_slot, rewind or quit
+                              (may be repeated)
+    --key-wait <press|release>
+                              When Fx0A resumes after a key is pressed
+    --font <name>             Small font for Fx29: standard, cosmac_vip,
+                              dream6800 or eti660
+    --big-font <name>         Big font for Fx30: octo (0-F) or schip (0-9)
+    --font-file <path>        Load 16 small glyphs, optionally followed by 10
+                              or 16 big ones, from a file
+    --tone <hz>               Frequency of the sound timer's tone
+    --volume <0-100>          Volume of the sound timer's tone
+    --waveform <name>         square, triangle, sine or sawtooth
+    --seed <n>                Seed for Cxkk, so runs can be reproduced (default:
+                              picked at random and printed)
+    --rng <name>              Cxkk generator: xorshift, or cosmac_vip to use
+                              the original interpreter's routine
+    --record <path>           Record keypad input to a movie file
+    --play <path>             Play back a movie, using its settings and input
+    --rewind <seconds>        Length of the rewind history, played back while
+                              Backspace is held (default 10, 0 disables)
+    --trace <path>            Log executed instructions to a tab-separated file
+    --trace-pc <start>[-<end>]
+                              Only trace instructions in this address range
+                              (may be repeated)
+    --trace-op <pattern>      Only trace opcodes matching a pattern such as
+                              Dxyn or Fx1E (may be repeated)";
+
+// Settings a configuration file may give outside of a section, and in [audio]
+const CONFIG_KEYS: [&'static str; 13] = ["romdb", "platform", "key_wait", "ipf", "load_addr",
+                                         "keymap", "colors", "rewind_seconds", "seed", "rng",
+                                         "font", "big_font", "font_file"];
+const AUDIO_CONFIG_KEYS: [&'static str; 3] = ["tone", "volume", "waveform"];
+
+pub struct Options {
+    pub rom_path: String,
+    pub rom: Rom,
+    // What the ROM database knows about the ROM
+    pub rom_info: RomInfo,
+    pub debug: bool,
+    pub gdb_port: Option<u16>,
+    pub key_wait_policy: KeyWaitPolicy,
+    pub quirks: Quirks,
+    // SUPER-CHIP instructions are allowed
+    pub schip: bool,
+    pub xo_chip: bool,
+    pub fonts: FontSet,
+    pub audio: AudioSettings,
+    pub instructions_per_frame: usize,
+    pub rewind_seconds: usize,
+    pub seed: Option<u32>,
+    pub random_mode: RandomMode,
+    pub record_movie: Option<String>,
+    pub play_movie: Option<String>,
+    pub trace_path: Option<String>,
+    pub trace_filter: TraceFilter,
+    pub keymap: Keymap,
+    pub controller_map: ControllerMap,
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut debug = false;
+        let mut gdb_port = None;
+        let mut config_path = None;
+        let mut romdb_paths = Vec::new();
+        let mut platform = None;
+        let mut quirk_overrides = Vec::new();
+        let mut keymap_name = None;
+        let mut key_overrides = Vec::new();
+        let mut button_overrides = Vec::new();
+        let mut key_wait = None;
+        let mut ipf = None;
+        let mut load_addr = None;
+        let mut small_font = None;
+        let mut big_font = None;
+        let mut font_file = None;
+        let mut tone = None;
+        let mut volume = None;
+        let mut waveform = None;
+        let mut rewind = None;
+        let mut seed = None;
+        let mut rng = None;
+        let mut record_movie = None;
+        let mut play_movie = None;
+        let mut trace_path = None;
+        let mut trace_filter = TraceFilter::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-d" => debug = true,
+                "--gdb" => gdb_port = Some(try!(expect_value(&mut args, &arg))),
+                "--config" => config_path = Some(try!(expect_value(&mut args, &arg))),
+                "--romdb" => romdb_paths.push(try!(expect_value(&mut args, &arg))),
+                "--platform" => platform = Some(try!(expect_value(&mut args, &arg))),
+                "--quirk" => quirk_overrides.push(try!(expect_value(&mut args, &arg))),
+                "--keymap" => keymap_name = Some(try!(expect_value(&mut args, &arg))),
+                "--bind" => {
+                    let binding = try!(expect_value(&mut args, &arg));
+                    key_overrides.push(try!(parse_binding(&binding)));
+                }
+                "--button" => button_overrides.push(try!(expect_value(&mut args, &arg))),
+                "--key-wait" => key_wait = Some(try!(expect_value(&mut args, &arg))),
+                "--ipf" => ipf = Some(try!(expect_value(&mut args, &arg))),
+                "--load-addr" => load_addr = Some(try!(expect_value(&mut args, &arg))),
+                "--font" => small_font = Some(try!(expect_value(&mut args, &arg))),
+                "--big-font" => big_font = Some(try!(expect_value(&mut args, &arg))),
+                "--font-file" => font_file = Some(try!(expect_value(&mut args, &arg))),
+                "--tone" => tone = Some(try!(expect_value(&mut args, &arg))),
+                "--volume" => volume = Some(try!(expect_value(&mut args, &arg))),
+                "--waveform" => waveform = Some(try!(expect_value(&mut args, &arg))),
+                "--seed" => seed = Some(try!(expect_value(&mut args, &arg))),
+                "--rng" => rng = Some(try!(expect_value(&mut args, &arg))),
+                "--record" => record_movie = Some(try!(expect_value(&mut args, &arg))),
+                "--play" => play_movie = Some(try!(expect_value(&mut args, &arg))),
+                "--rewind" => rewind = Some(try!(expect_value(&mut args, &arg))),
+                "--trace" => trace_path = Some(try!(expect_value(&mut args, &arg))),
+                "--trace-pc" => {
+                    let range = try!(expect_value(&mut args, &arg));
+                    trace_filter.pc_ranges.push(try!(parse_range(&range)));
+                }
+                "--trace-op" => {
+                    let pattern = try!(expect_value(&mut args, &arg));
+                    let pattern = try!(OpcodePattern::parse(&pattern)
+                                           .ok_or(format!("Invalid opcode pattern '{}'", pattern)));
+                    trace_filter.opcodes.push(pattern);
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => rom_path = Some(arg),
+            }
+        }
+
+        let config = match config_path {
+            Some(path) => try!(Config::load(Path::new(&path))),
+            None => Config::empty(),
+        };
+        try!(check_config(&config));
+
+        let rom_path = try!(rom_path.ok_or(USAGE.to_string()));
+        let mut rom = try!(Rom::from_path(Path::new(&rom_path))
+                               .map_err(|e| format!("Could not open {}: {}", rom_path, e)));
+
+        // The ROM database fills in whatever the command line and configuration leave unset
+        if let Some(path) = config.get("", "romdb") {
+            romdb_paths.insert(0, path.to_string());
+        }
+        let rom_info = try!(try!(RomDatabase::load(&romdb_paths)).lookup(&rom.sha1()));
+
+        let user_platform = platform.or(config.get("", "platform").map(|s| s.to_string()));
+        let platform = user_platform.clone().or(rom_info.platform.clone());
+        let platform_name = platform.as_ref().map(|s| s.as_str());
+        let xo_chip = platform_name == Some("xo_chip");
+        let schip = xo_chip || platform_name == Some("schip_legacy") ||
+                    platform_name == Some("schip_modern");
+        let mut quirks = match platform {
+            Some(name) => {
+                try!(Quirks::from_preset(&name).ok_or(format!("Unknown platform '{}', expected one of: {}",
+                                                              name,
+                                                              quirks::PRESET_NAMES.join(", "))))
+            }
+            None => Quirks::default(),
+        };
+
+        // The database's quirks go with its platform, so they're skipped if the user
+        // picked another one
+        if user_platform.is_none() {
+            for &(ref name, ref value) in &rom_info.quirks {
+                let value = try!(quirks::parse_bool(value)
+                                     .map_err(|e| format!("ROM database: {}", e)));
+                try!(quirks.set(name, value).map_err(|e| format!("ROM database: {}", e)));
+            }
+        }
+
+        for entry in config.section("quirks") {
+            let value = try!(quirks::parse_bool(&entry.value)
+                                 .map_err(|e| format!("config line {}: {}", entry.line, e)));
+            try!(quirks.set(&entry.key, value)
+                       .map_err(|e| format!("config line {}: {}", entry.line, e)));
+        }
+
+        for setting in quirk_overrides {
+            try!(quirks.apply_override(&setting));
+        }
+
+        let key_wait = key_wait.or(config.get("", "key_wait").map(|s| s.to_string()));
+        let key_wait_policy = match key_wait {
+            Some(name) => {
+                try!(KeyWaitPolicy::from_name(&name)
+                         .ok_or(format!("key wait expects 'press' or 'release', got '{}'", name)))
+            }
+            None => KeyWaitPolicy::default(),
+        };
+
+        let ipf = ipf.or(config.get("", "ipf").map(|s| s.to_string()))
+                     .or(rom_info.instructions_per_frame.clone());
+        let instructions_per_frame = match ipf {
+            Some(ipf) => {
+                try!(ipf.parse::<usize>()
+                        .ok()
+                        .and_then(|n| if n > 0 { Some(n) } else { None })
+                        .ok_or(format!("Invalid instructions per frame '{}'", ipf)))
+            }
+            None => DEFAULT_INSTRUCTIONS_PER_FRAME,
+        };
+
+        let load_addr = load_addr.or(config.get("", "load_addr").map(|s| s.to_string()))
+                                 .or(rom_info.load_addr.clone());
+        rom.set_load_addr(match load_addr {
+            Some(addr) => {
+                try!(parse_address(&addr).ok_or(format!("Invalid load address '{}'", addr)))
+            }
+            None => DEFAULT_LOAD_ADDR,
+        });
+
+        let keymap_name = keymap_name.or(config.get("", "keymap").map(|s| s.to_string()))
+                                     .or(rom_info.keymap.clone())
+                                     .unwrap_or("qwerty".to_string());
+        let mut keymap = try!(load_keymap(&config, &keymap_name));
+
+        // Each layer of bindings replaces the keys of the layers before it for the
+        // CHIP-8 keys it mentions
+        let mut game_keys = Vec::new();
+        for &(ref key, ref host_key) in &rom_info.keys {
+            let key = try!(keymap::parse_key(key)
+                               .ok_or(format!("ROM database: invalid CHIP-8 key '{}'", key)));
+            game_keys.push((key, host_key.clone()));
+        }
+        keymap.apply(&game_keys);
+
+        let mut config_keys = Vec::new();
+        for entry in config.section("keys") {
+            let key = try!(keymap::parse_key(&entry.key)
+                               .ok_or(format!("config line {}: invalid CHIP-8 key '{}'",
+                                              entry.line,
+                                              entry.key)));
+            config_keys.push((key, entry.value.clone()));
+        }
+        keymap.apply(&config_keys);
+        keymap.apply(&key_overrides);
+
+        let mut controller_map = ControllerMap::default();
+        for &(ref button, ref action) in &rom_info.buttons {
+            try!(controller_map.bind(button, action).map_err(|e| format!("ROM database: {}", e)));
+        }
+        for entry in config.section("controller") {
+            try!(controller_map.bind(&entry.key, &entry.value)
+                               .map_err(|e| format!("config line {}: {}", entry.line, e)));
+        }
+        for setting in button_overrides {
+            let mut parts = setting.splitn(2, '=');
+            let button = parts.next().unwrap_or("").trim();
+            let action = try!(parts.next().ok_or(format!("Invalid button binding '{}', expected \
+                                                           <button>=<action>",
+                                                          setting)));
+            try!(controller_map.bind(button, action.trim()));
+        }
+
+        let colors = config.get("", "colors").map(|s| s.to_string()).or(rom_info.colors.clone());
+        let colors = match colors {
+            Some(colors) => try!(parse_colors(&colors)),
+            None => Vec::new(),
+        };
+
+        let rewind = rewind.or(config.get("", "rewind_seconds").map(|s| s.to_string()));
+        let rewind_seconds = match rewind {
+            Some(seconds) => {
+                try!(seconds.parse::<usize>()
+                            .map_err(|_| format!("Invalid rewind length '{}'", seconds)))
+            }
+            None => DEFAULT_REWIND_SECONDS,
+        };
+
+        let seed = seed.or(config.get("", "seed").map(|s| s.to_string()));
+        let seed = match seed {
+            Some(seed) => {
+                Some(try!(seed.parse::<u32>().map_err(|_| format!("Invalid seed '{}'", seed))))
+            }
+            None => None,
+        };
+
+        let rng = rng.or(config.get("", "rng").map(|s| s.to_string()));
+        let random_mode = match rng {
+            Some(name) => {
+                try!(RandomMode::from_name(&name)
+                         .ok_or(format!("Unknown generator '{}', expected xorshift or cosmac_vip",
+                                        name)))
+            }
+            None => RandomMode::default(),
+        };
+
+        let mut fonts = FontSet::default();
+        if let Some(name) = small_font.or(config.get("", "font").map(|s| s.to_string())) {
+            try!(fonts.set_small(&name));
+        }
+        if let Some(name) = big_font.or(config.get("", "big_font").map(|s| s.to_string())) {
+            try!(fonts.set_big(&name));
+        }
+        if let Some(path) = font_file.or(config.get("", "font_file").map(|s| s.to_string())) {
+            try!(fonts.load_file(Path::new(&path))
+                      .map_err(|e| format!("Could not load font {}: {}", path, e)));
+        }
+
+        let mut audio = AudioSettings::default();
+
+        let tone = tone.or(config.get("audio", "tone").map(|s| s.to_string()));
+        if let Some(tone) = tone {
+            audio.frequency = try!(tone.parse::<f32>()
+                                       .ok()
+                                       .and_then(|hz| if hz > 0.0 { Some(hz) } else { None })
+                                       .ok_or(format!("Invalid tone frequency '{}'", tone)));
+        }
+
+        let volume = volume.or(config.get("audio", "volume").map(|s| s.to_string()));
+        if let Some(volume) = volume {
+            let percent = try!(volume.parse::<u8>()
+                                     .ok()
+                                     .and_then(|v| if v <= 100 { Some(v) } else { None })
+                                     .ok_or(format!("Volume should be 0-100, got '{}'", volume)));
+            audio.volume = percent as f32 / 100.0;
+        }
+
+        let waveform = waveform.or(config.get("audio", "waveform").map(|s| s.to_string()));
+        if let Some(waveform) = waveform {
+            audio.waveform = try!(Waveform::from_name(&waveform)
+                                      .ok_or(format!("Unknown waveform '{}'", waveform)));
+        }
+
+        let gdb_port = match gdb_port {
+            Some(port) => {
+                Some(try!(port.parse::<u16>()
+                              .ok()
+                              .and_then(|p| if p > 0 { Some(p) } else { None })
+                              .ok_or(format!("Invalid GDB port '{}'", port))))
+            }
+            None => None,
+        };
+        if debug && gdb_port.is_some() {
+            return Err("-d and --gdb cannot be used together".to_string());
+        }
+
+        if record_movie.is_some() && play_movie.is_some() {
+            return Err("--record and --play cannot be used together".to_string());
+        }
+
+        if trace_path.is_none() &&
+           (!trace_filter.pc_ranges.is_empty() || !trace_filter.opcodes.is_empty()) {
+            return Err("--trace-pc and --trace-op need --trace".to_string());
+        }
+
+        Ok(Options {
+            rom_path: rom_path,
+            rom: rom,
+            rom_info: rom_info,
+            debug: debug,
+            gdb_port: gdb_port,
+            key_wait_policy: key_wait_policy,
+            quirks: quirks,
+            schip: schip,
+            xo_chip: xo_chip,
+            fonts: fonts,
+            audio: audio,
+            instructions_per_frame: instructions_per_frame,
+            rewind_seconds: rewind_seconds,
+            seed: seed,
+            random_mode: random_mode,
+            record_movie: record_movie,
+            play_movie: play_movie,
+            trace_path: trace_path,
+            trace_filter: trace_filter,
+            keymap: keymap,
+            controller_map: controller_map,
+            colors: colors,
+        })
+    }
+}
+
+// `start-end` inclusive, or a single address
+fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    let mut parts = text.splitn(2, '-');
+    let start = parts.next().and_then(parse_address);
+    let end = match parts.next() {
+        Some(end) => parse_address(end),
+        None => start,
+    };
+
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => Ok((start, end)),
+        _ => Err(format!("Invalid address range '{}'", text)),
+    }
+}
+
+// Two colours (off and on) or all four XO-CHIP plane combinations, as RRGGBB
+fn parse_colors(text: &str) -> Result<Vec<(u8, u8, u8)>, String> {
+    let mut colors = Vec::new();
+    for color in text.split_whitespace() {
+        let value = try!(u32::from_str_radix(color, 16)
+                             .ok()
+                             .and_then(|v| if color.len() == 6 { Some(v) } else { None })
+                             .ok_or(format!("Invalid colour '{}', expected RRGGBB", color)));
+        colors.push(((value >> 16) as u8, (value >> 8) as u8, value as u8));
+    }
+
+    if colors.len() != 2 && colors.len() != 4 {
+        return Err(format!("Expected 2 or 4 colours, got '{}'", text));
+    }
+    Ok(colors)
+}
+
+// Rejects keys nothing reads, so a misspelt setting isn't silently ignored. The
+// quirks, keys, controller and keymap sections are checked as they're applied.
+fn check_config(config: &Config) -> Result<(), String> {
+    for entry in config.entries() {
+        let known = match entry.section.as_str() {
+            "" => CONFIG_KEYS.contains(&entry.key.as_str()),
+            "audio" => AUDIO_CONFIG_KEYS.contains(&entry.key.as_str()),
+            "quirks" | "keys" | "controller" => true,
+            section => section.starts_with("keymap."),
+        };
+        if !known {
+            let name = if entry.section.is_empty() {
+                entry.key.clone()
+            } else {
+                format!("{}.{}", entry.section, entry.key)
+            };
+            return Err(format!("config line {}: unknown setting '{}'", entry.line, name));
+        }
+    }
+    Ok(())
+}
+
+// A built-in profile, unless the configuration defines one with that name
+fn load_keymap(config: &Config, name: &str) -> Result<Keymap, String> {
+    let section = format!("keymap.{}", name);
+    let entries = config.section(&section);
+    if entries.is_empty() {
+        return Keymap::from_profile(name).ok_or(format!("Unknown keymap '{}', expected one of: \
+                                                         {}, or a [{}] configuration section",
+                                                        name,
+                                                        keymap::PROFILE_NAMES.join(", "),
+                                                        section));
+    }
+
+    let mut keymap = Keymap::default();
+    for entry in entries {
+        let key = try!(keymap::parse_key(&entry.key)
+                           .ok_or(format!("config line {}: invalid CHIP-8 key '{}'",
+                                          entry.line,
+                                          entry.key)));
+        keymap.bind(key, &entry.value);
+    }
+    Ok(keymap)
+}
+
+// `5=Up` binds the Up arrow to CHIP-8 key 5
+fn parse_binding(text: &str) -> Result<(u8, String), String> {
+    let mut parts = text.splitn(2, '=');
+    let key = parts.next().unwrap_or("").trim();
+    let host_key = parts.next().unwrap_or("").trim();
+    if host_key.is_empty() {
+        return Err(format!("Invalid key binding '{}', expected <key>=<host key>", text));
+    }
+
+    let key = try!(keymap::parse_key(key).ok_or(format!("Invalid CHIP-8 key '{}'", key)));
+    Ok((key, host_key.to_string()))
+}
+
+fn expect_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next().ok_or(format!("{} expects a value", flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_config_accepts_every_known_setting() {
+        let config = Config::parse("platform = xo_chip\nseed = 1\n[audio]\ntone = 220\n[quirks]\n\
+                                    clip_sprites = on\n[keymap.mine]\n5 = Up\n")
+                         .unwrap();
+        assert!(check_config(&config).is_ok());
+    }
+
+    #[test]
+    fn check_config_rejects_unknown_settings() {
+        let config = Config::parse("plaform = xo_chip\n").unwrap();
+        assert_eq!(check_config(&config).err(),
+                   Some("config line 1: unknown setting 'plaform'".to_string()));
+
+        let config = Config::parse("[audio]\nvolume = 50\npitch = 3\n").unwrap();
+        assert_eq!(check_config(&config).err(),
+                   Some("config line 3: unknown setting 'audio.pitch'".to_string()));
+
+        let config = Config::parse("[sound]\nvolume = 50\n").unwrap();
+        assert!(check_config(&config).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use config::Config;
+use cpu::rom::Sha1Digest;
+
+use std::path::Path;
+
+const BUNDLED: &'static str = include_str!("romdb.ini");
+
+// What the database knows about one ROM. Values are kept as written and checked by
+// whoever applies them, like configuration file values.
+#[derive(Default)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Vec<(String, String)>,
+    pub instructions_per_frame: Option<String>,
+    pub load_addr: Option<String>,
+    pub keymap: Option<String>,
+    // CHIP-8 key digit and host key name
+    pub keys: Vec<(String, String)>,
+    // Controller button name and action
+    pub buttons: Vec<(String, String)>,
+    pub colors: Option<String>,
+}
+
+// The bundled database followed by any local ones, which override it key by key
+pub struct RomDatabase {
+    layers: Vec<Config>,
+}
+
+impl RomDatabase {
+    pub fn load(local_paths: &[String]) -> Result<RomDatabase, String> {
+        let mut layers = vec![try!(Config::parse(BUNDLED)
+                                       .map_err(|e| format!("bundled ROM database: {}", e)))];
+        for path in local_paths {
+            layers.push(try!(Config::load(Path::new(path))));
+        }
+        Ok(RomDatabase { layers: layers })
+    }
+
+    pub fn lookup(&self, sha1: &Sha1Digest) -> Result<RomInfo, String> {
+        let section = sha1.to_string();
+        let mut info = RomInfo::default();
+
+        for layer in &self.layers {
+            for entry in layer.section(&section) {
+                let value = Some(entry.value.clone());
+                match entry.key.as_str() {
+                    "title" => info.title = value,
+                    "author" => info.author = value,
+                    "platform" => info.platform = value,
+                    "ipf" => info.instructions_per_frame = value,
+                    "load_addr" => info.load_addr = value,
+                    "colors" => info.colors = value,
+                    "keymap" => info.keymap = value,
+                    key if key.starts_with("quirk.") => {
+                        info.quirks.push((key["quirk.".len()..].to_string(), entry.value.clone()))
+                    }
+                    key if key.starts_with("key.") => {
+                        info.keys.push((key["key.".len()..].to_string(), entry.value.clone()))
+                    }
+                    key if key.starts_with("button.") => {
+                        info.buttons.push((key["button.".len()..].to_string(), entry.value.clone()))
+                    }
+                    key => {
+                        return Err(format!("ROM database line {}: unknown key '{}'",
+                                           entry.line,
+                                           key))
+                    }
+                }
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::rom;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    fn sha1_of(data: &[u8]) -> Sha1Digest {
+        Sha1Digest(rom::sha1(data))
+    }
+
+    #[test]
+    fn lookup_finds_bundled_roms_by_sha1() {
+        let database = RomDatabase::load(&[]).unwrap();
+        let info = database.lookup(&sha1_of(include_bytes!("../tests/fixtures/xo_chip.ch8")))
+                           .unwrap();
+
+        assert_eq!(info.title.as_ref().map(|s| s.as_str()), Some("XO-CHIP instruction test"));
+        assert_eq!(info.platform.as_ref().map(|s| s.as_str()), Some("xo_chip"));
+    }
+
+    #[test]
+    fn lookup_knows_nothing_about_other_roms() {
+        let database = RomDatabase::load(&[]).unwrap();
+        let info = database.lookup(&sha1_of(&[0x12, 0x00])).unwrap();
+
+        assert!(info.title.is_none());
+        assert!(info.platform.is_none());
+    }
+
+    #[test]
+    fn local_databases_override_the_bundled_one() {
+        let sha1 = sha1_of(include_bytes!("../tests/fixtures/schip.ch8"));
+        let path = env::temp_dir().join("rust8-local-romdb.ini");
+        File::create(&path)
+            .and_then(|mut f| write!(f, "[{}]\nplatform = schip_legacy\nkey.5 = Up\n", sha1))
+            .unwrap();
+
+        let database = RomDatabase::load(&[path.to_string_lossy().into_owned()]);
+        fs::remove_file(&path).unwrap();
+        let info = database.unwrap().lookup(&sha1).unwrap();
+
+        assert_eq!(info.title.as_ref().map(|s| s.as_str()), Some("SUPER-CHIP instruction test"));
+        assert_eq!(info.platform.as_ref().map(|s| s.as_str()), Some("schip_legacy"));
+        assert_eq!(info.keys, vec![("5".to_string(), "Up".to_string())]);
+    }
+
+
+    #[test]
+    fn load_fails_on_a_missing_local_database() {
+        let path = env::temp_dir().join("rust8-missing-romdb.ini");
+        assert!(RomDatabase::load(&[path.to_string_lossy().into_owned()]).is_err());
+    }
+}